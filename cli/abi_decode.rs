@@ -0,0 +1,470 @@
+use serde_json::{Map, Value};
+use tiny_keccak::{Hasher, Keccak};
+
+/// solidity type of a constructor input, parsed from its ABI `type` and `components`
+#[derive(Debug, Clone)]
+enum ParamType {
+    Address,
+    Bool,
+    Uint,
+    Int,
+    FixedBytes(usize),
+    Bytes,
+    String,
+    Array(Box<ParamType>),
+    FixedArray(Box<ParamType>, usize),
+    Tuple(Vec<(String, ParamType)>),
+}
+
+impl ParamType {
+    fn parse(r#type: &str, components: Option<&Value>) -> Result<ParamType, String> {
+        if let Some(inner) = r#type.strip_suffix(']') {
+            let open = inner
+                .rfind('[')
+                .ok_or_else(|| format!("invalid type {}", r#type))?;
+            let element = Box::new(ParamType::parse(&inner[..open], components)?);
+            let size = &inner[open + 1..];
+            return if size.is_empty() {
+                Ok(ParamType::Array(element))
+            } else {
+                let size = size
+                    .parse()
+                    .map_err(|_| format!("invalid type {}", r#type))?;
+                Ok(ParamType::FixedArray(element, size))
+            };
+        }
+        match r#type {
+            "address" => Ok(ParamType::Address),
+            "bool" => Ok(ParamType::Bool),
+            "string" => Ok(ParamType::String),
+            "bytes" => Ok(ParamType::Bytes),
+            "tuple" => {
+                let components = components
+                    .and_then(Value::as_array)
+                    .ok_or_else(|| "tuple without components".to_string())?;
+                Ok(ParamType::Tuple(parse_params(components)?))
+            }
+            // enums and contracts are encoded as their underlying type, only the internal type differs
+            _ if r#type.starts_with("uint") => Ok(ParamType::Uint),
+            _ if r#type.starts_with("int") => Ok(ParamType::Int),
+            _ if r#type.starts_with("bytes") => r#type[5..]
+                .parse()
+                .ok()
+                .filter(|size| (1..=32).contains(size))
+                .map(ParamType::FixedBytes)
+                .ok_or_else(|| format!("invalid type {}", r#type)),
+            _ => Err(format!("unsupported type {}", r#type)),
+        }
+    }
+
+    fn is_dynamic(&self) -> bool {
+        match self {
+            ParamType::Bytes | ParamType::String | ParamType::Array(_) => true,
+            ParamType::FixedArray(element, _) => element.is_dynamic(),
+            ParamType::Tuple(fields) => fields.iter().any(|(_, field)| field.is_dynamic()),
+            _ => false,
+        }
+    }
+
+    /// size taken in the head of the enclosing sequence
+    fn head_size(&self) -> usize {
+        if self.is_dynamic() {
+            return 32;
+        }
+        match self {
+            ParamType::FixedArray(element, size) => element.head_size() * size,
+            ParamType::Tuple(fields) => fields.iter().map(|(_, field)| field.head_size()).sum(),
+            _ => 32,
+        }
+    }
+}
+
+fn parse_params(inputs: &[Value]) -> Result<Vec<(String, ParamType)>, String> {
+    inputs
+        .iter()
+        .map(|input| {
+            let name = input
+                .get("name")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            let r#type = input
+                .get("type")
+                .and_then(Value::as_str)
+                .ok_or_else(|| format!("input {} has no type", name))?;
+            Ok((name, ParamType::parse(r#type, input.get("components"))?))
+        })
+        .collect()
+}
+
+/// decode the ABI encoded `args_data` with the inputs of the constructor found in `abi`
+/// numbers are given as decimal strings, bytes and addresses as hex strings and tuples as objects keyed by field name
+pub fn decode_constructor_args(abi: &[Value], args_data: &str) -> Result<Vec<Value>, String> {
+    let inputs = abi
+        .iter()
+        .find(|entry| entry.get("type").and_then(Value::as_str) == Some("constructor"))
+        .and_then(|constructor| constructor.get("inputs"))
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let params = parse_params(inputs)?;
+    let data = decode_hex(args_data)?;
+
+    let types: Vec<&ParamType> = params.iter().map(|(_, param)| param).collect();
+    let (values, end) = decode_sequence(&data, 0, &types)?;
+    // the encoding of the last dynamic value is not followed by anything, so only static arguments can be checked
+    if params.iter().all(|(_, param)| !param.is_dynamic()) && end != data.len() {
+        return Err(format!(
+            "expected {} bytes of arguments, got {}",
+            end,
+            data.len()
+        ));
+    }
+    Ok(values)
+}
+
+/// decode the values of a head/tail encoded sequence starting at `start`
+/// also return where the heads end
+fn decode_sequence(
+    data: &[u8],
+    start: usize,
+    types: &[&ParamType],
+) -> Result<(Vec<Value>, usize), String> {
+    let mut head = start;
+    let mut values = Vec::new();
+    for param in types {
+        let at = if param.is_dynamic() {
+            start
+                .checked_add(read_usize(data, head)?)
+                .ok_or_else(|| "invalid offset".to_string())?
+        } else {
+            head
+        };
+        values.push(decode_value(data, at, param)?);
+        head += param.head_size();
+    }
+    Ok((values, head))
+}
+
+fn decode_value(data: &[u8], at: usize, param: &ParamType) -> Result<Value, String> {
+    Ok(match param {
+        ParamType::Address => Value::String(to_checksum_address(&word(data, at)?[12..])),
+        ParamType::Bool => Value::Bool(word(data, at)?[31] != 0),
+        ParamType::Uint => Value::String(to_decimal(word(data, at)?)),
+        ParamType::Int => {
+            let value = word(data, at)?;
+            if value[0] & 0x80 == 0 {
+                Value::String(to_decimal(value))
+            } else {
+                Value::String(format!("-{}", to_decimal(&negate(value))))
+            }
+        }
+        ParamType::FixedBytes(size) => Value::String(to_hex(&word(data, at)?[..*size])),
+        ParamType::Bytes => Value::String(to_hex(read_bytes(data, at)?)),
+        ParamType::String => {
+            Value::String(String::from_utf8_lossy(read_bytes(data, at)?).to_string())
+        }
+        ParamType::Array(element) => {
+            let length = read_usize(data, at)?;
+            // every element takes at least a word, bail out before allocating for a bogus length
+            if length > data.len() / 32 {
+                return Err(format!("invalid array length {}", length));
+            }
+            let types = vec![element.as_ref(); length];
+            Value::Array(decode_sequence(data, at + 32, &types)?.0)
+        }
+        ParamType::FixedArray(element, size) => {
+            let types = vec![element.as_ref(); *size];
+            Value::Array(decode_sequence(data, at, &types)?.0)
+        }
+        ParamType::Tuple(fields) => {
+            let types: Vec<&ParamType> = fields.iter().map(|(_, field)| field).collect();
+            let values = decode_sequence(data, at, &types)?.0;
+            if fields.iter().any(|(name, _)| name.is_empty()) {
+                Value::Array(values)
+            } else {
+                let mut object = Map::new();
+                for ((name, _), value) in fields.iter().zip(values) {
+                    object.insert(name.to_string(), value);
+                }
+                Value::Object(object)
+            }
+        }
+    })
+}
+
+fn word(data: &[u8], at: usize) -> Result<&[u8], String> {
+    data.get(at..at.saturating_add(32))
+        .ok_or_else(|| format!("arguments are too short, expected a word at byte {}", at))
+}
+
+fn read_usize(data: &[u8], at: usize) -> Result<usize, String> {
+    let value = word(data, at)?;
+    if value[..24].iter().any(|byte| *byte != 0) {
+        return Err(format!("invalid offset or length at byte {}", at));
+    }
+    Ok(u64::from_be_bytes(value[24..].try_into().unwrap()) as usize)
+}
+
+fn read_bytes(data: &[u8], at: usize) -> Result<&[u8], String> {
+    let length = read_usize(data, at)?;
+    data.get(at + 32..(at + 32).saturating_add(length))
+        .ok_or_else(|| {
+            format!(
+                "arguments are too short, expected {} bytes at byte {}",
+                length,
+                at + 32
+            )
+        })
+}
+
+/// two's complement of a negative int
+fn negate(value: &[u8]) -> Vec<u8> {
+    let mut result: Vec<u8> = value.iter().map(|byte| !byte).collect();
+    for byte in result.iter_mut().rev() {
+        let (sum, overflow) = byte.overflowing_add(1);
+        *byte = sum;
+        if !overflow {
+            break;
+        }
+    }
+    result
+}
+
+/// decimal representation of a big endian unsigned integer
+fn to_decimal(value: &[u8]) -> String {
+    let mut number = value.to_vec();
+    let mut digits = Vec::new();
+    while number.iter().any(|byte| *byte != 0) {
+        let mut remainder = 0u32;
+        for byte in number.iter_mut() {
+            let current = (remainder << 8) | *byte as u32;
+            *byte = (current / 10) as u8;
+            remainder = current % 10;
+        }
+        digits.push(char::from(b'0' + remainder as u8));
+    }
+    if digits.is_empty() {
+        return "0".to_string();
+    }
+    digits.iter().rev().collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("0x{}", hex)
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>, String> {
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    if !value.len().is_multiple_of(2) {
+        return Err(format!("invalid hex {}", value));
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&value[i..i + 2], 16).map_err(|_| format!("invalid hex {}", value))
+        })
+        .collect()
+}
+
+/// EIP-55 mixed case address, as forge writes them
+fn to_checksum_address(address: &[u8]) -> String {
+    let lowercase: String = address.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let mut hash = [0u8; 32];
+    let mut hasher = Keccak::v256();
+    hasher.update(lowercase.as_bytes());
+    hasher.finalize(&mut hash);
+    let checksummed: String = lowercase
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = (hash[i / 2] >> if i % 2 == 0 { 4 } else { 0 }) & 0x0f;
+            if nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect();
+    format!("0x{}", checksummed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn constructor(inputs: Value) -> Vec<Value> {
+        vec![json!({"type": "constructor", "inputs": inputs, "stateMutability": "nonpayable"})]
+    }
+
+    /// `0x` followed by the words, each given as hex and left padded to 32 bytes
+    fn encode(words: &[&str]) -> String {
+        let data: String = words.iter().map(|word| format!("{:0>64}", word)).collect();
+        format!("0x{}", data)
+    }
+
+    /// hex of an utf8 string right padded to a multiple of 32 bytes
+    fn padded(value: &str) -> String {
+        let hex: String = value.bytes().map(|byte| format!("{:02x}", byte)).collect();
+        let len = hex.len().div_ceil(64).max(1) * 64;
+        format!("{:0<1$}", hex, len)
+    }
+
+    #[test]
+    fn decodes_static_arguments() {
+        let abi = constructor(json!([
+            {"name": "owner", "type": "address"},
+            {"name": "amount", "type": "uint256"},
+            {"name": "enabled", "type": "bool"},
+            {"name": "id", "type": "bytes4"}
+        ]));
+        let data = encode(&[
+            "d8da6bf26964af9d7eed9e03e53415d37aa96045",
+            "de0b6b3a7640000",
+            "1",
+            "12345678",
+        ]);
+        // bytes4 are left aligned
+        let data = data.replace(
+            &format!("{:0>64}", "12345678"),
+            &format!("{:0<64}", "12345678"),
+        );
+        assert_eq!(
+            decode_constructor_args(&abi, &data).unwrap(),
+            vec![
+                json!("0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045"),
+                json!("1000000000000000000"),
+                json!(true),
+                json!("0x12345678")
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_dynamic_arguments() {
+        let abi = constructor(json!([
+            {"name": "prefix", "type": "string"},
+            {"name": "count", "type": "uint8"},
+            {"name": "data", "type": "bytes"}
+        ]));
+        let data = format!(
+            "{}{}{}{:0<64}",
+            encode(&["60", "7", "a0", "5"]),
+            padded("hello"),
+            &encode(&["2"])[2..],
+            "abcd"
+        );
+        assert_eq!(
+            decode_constructor_args(&abi, &data).unwrap(),
+            vec![json!("hello"), json!("7"), json!("0xabcd")]
+        );
+    }
+
+    #[test]
+    fn decodes_large_and_negative_integers() {
+        let abi = constructor(json!([
+            {"name": "max", "type": "uint256"},
+            {"name": "minusTwo", "type": "int256"},
+            {"name": "min", "type": "int8"}
+        ]));
+        let data = encode(&[
+            &"f".repeat(64),
+            &format!("{}e", "f".repeat(63)),
+            &format!("{}80", "f".repeat(62)),
+        ]);
+        assert_eq!(
+            decode_constructor_args(&abi, &data).unwrap(),
+            vec![
+                json!(
+                    "115792089237316195423570985008687907853269984665640564039457584007913129639935"
+                ),
+                json!("-2"),
+                json!("-128")
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_named_and_unnamed_nested_tuples() {
+        let abi = constructor(json!([{
+            "name": "config",
+            "type": "tuple",
+            "components": [
+                {"name": "label", "type": "string"},
+                {
+                    "name": "limits",
+                    "type": "tuple",
+                    "components": [
+                        {"name": "", "type": "uint256"},
+                        {"name": "", "type": "uint256"}
+                    ]
+                }
+            ]
+        }]));
+        // the tuple is dynamic because of its string, its static inner tuple is encoded in place
+        let data = format!("{}{}", encode(&["20", "60", "1", "2", "3"]), padded("abc"));
+        assert_eq!(
+            decode_constructor_args(&abi, &data).unwrap(),
+            vec![json!({"label": "abc", "limits": ["1", "2"]})]
+        );
+    }
+
+    #[test]
+    fn decodes_fixed_and_dynamic_arrays() {
+        let abi = constructor(json!([
+            {"name": "fixed", "type": "uint16[2]"},
+            {"name": "dynamic", "type": "string[]"}
+        ]));
+        let data = format!(
+            "{}{}{}{}",
+            encode(&["1", "2", "60", "2", "40", "80", "1"]),
+            padded("a"),
+            &encode(&["2"])[2..],
+            padded("bc")
+        );
+        assert_eq!(
+            decode_constructor_args(&abi, &data).unwrap(),
+            vec![json!(["1", "2"]), json!(["a", "bc"])]
+        );
+    }
+
+    #[test]
+    fn decodes_no_arguments() {
+        assert!(decode_constructor_args(&[], "0x").unwrap().is_empty());
+        assert!(decode_constructor_args(&constructor(json!([])), "")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn rejects_truncated_arguments() {
+        let abi = constructor(json!([
+            {"name": "a", "type": "uint256"},
+            {"name": "b", "type": "uint256"}
+        ]));
+        assert!(decode_constructor_args(&abi, &encode(&["1"])).is_err());
+        assert!(decode_constructor_args(&abi, &encode(&["1", "2", "3"])).is_err());
+    }
+
+    #[test]
+    fn rejects_bogus_offsets_and_lengths() {
+        let abi = constructor(json!([{"name": "s", "type": "string"}]));
+        // offset pointing past the end of the data
+        assert!(decode_constructor_args(&abi, &encode(&["100"])).is_err());
+        // offset that does not fit in 64 bits
+        assert!(decode_constructor_args(&abi, &encode(&[&"f".repeat(64)])).is_err());
+        // length larger than the remaining data
+        assert!(decode_constructor_args(&abi, &encode(&["20", "40", "0"])).is_err());
+
+        let abi = constructor(json!([{"name": "values", "type": "uint256[]"}]));
+        assert!(decode_constructor_args(&abi, &encode(&["20", "ffffffff"])).is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_types() {
+        let abi = constructor(json!([{"name": "f", "type": "function"}]));
+        assert!(decode_constructor_args(&abi, &encode(&["0"])).is_err());
+    }
+}
@@ -28,10 +28,13 @@ pub fn generate_deployer(
                     Ok(file) => {
                         if file.metadata().unwrap().is_file() {
                             let template_sub_path = file.path();
-                            let content = fs::read_to_string(&template_sub_path).expect(&format!(
-                                "Failed to read template {}",
-                                template_sub_path.display()
-                            ));
+                            let content =
+                                fs::read_to_string(&template_sub_path).unwrap_or_else(|_| {
+                                    panic!(
+                                        "Failed to read template {}",
+                                        template_sub_path.display()
+                                    )
+                                });
                             let template_name = template_name(&template_sub_path);
                             handlebars
                                 .register_template_string(&template_name, content)
@@ -44,11 +47,9 @@ pub fn generate_deployer(
                 }
             }
         } else {
-            let content = fs::read_to_string(&template_path).expect(&format!(
-                "Failed to read template {}",
-                template_path.display()
-            ));
-            let template_name = template_name(&template_path);
+            let content = fs::read_to_string(template_path)
+                .unwrap_or_else(|_| panic!("Failed to read template {}", template_path.display()));
+            let template_name = template_name(template_path);
             handlebars
                 .register_template_string(&template_name, content)
                 .unwrap();
@@ -63,12 +64,10 @@ pub fn generate_deployer(
 
     write_if_different(
         &format!("{}/DeployerFunctions.g.sol", folder_path),
-        format!(
-            "{}",
-            handlebars
-                .render("DeployerFunctions.g.sol", contracts)
-                .unwrap()
-        ),
+        handlebars
+            .render("DeployerFunctions.g.sol", contracts)
+            .unwrap()
+            .to_string(),
     );
 
     // for template_path in extra_templates_path {
@@ -81,7 +80,7 @@ pub fn generate_deployer(
     for template in templates {
         write_if_different(
             &format!("{}/{}", folder_path, template),
-            format!("{}", handlebars.render(&template, contracts).unwrap()),
+            handlebars.render(&template, contracts).unwrap().to_string(),
         );
     }
 }
@@ -120,7 +119,7 @@ fn memory_type(
     Ok(())
 }
 
-fn template_name(template_path: &PathBuf) -> String {
+fn template_name(template_path: &Path) -> String {
     let filename = template_path
         .file_name()
         .unwrap()
@@ -1,10 +1,16 @@
-use std::{collections::HashMap, fs, path::Path};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
 
-use crate::types::DeploymentObject;
+use crate::forge_returns::parse_deployer_deployments;
+use crate::types::{
+    DeployerDeployment, DeploymentCreation, DeploymentLog, DeploymentObject, DeploymentReceipt,
+    ProxyUpgrade,
+};
 
-use regex::Regex;
 use serde::{Deserialize, Serialize};
-use serde_json::Value::Object;
 use serde_json::{from_str, Value};
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
@@ -12,6 +18,7 @@ use serde_json::{from_str, Value};
 pub struct Transaction {
     r#type: String, // example: "0x02"
     from: String,
+    to: Option<String>,
     gas: String,           // example: "0xca531"
     value: Option<String>, // example:  "0x0"
     data: String,          // "0x..."
@@ -22,180 +29,910 @@ pub struct Transaction {
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 #[serde(rename_all(deserialize = "camelCase", serialize = "camelCase"))]
 pub struct TransactionResult {
-    hash: String,
+    hash: Option<String>,
     transaction_type: String, // CREATE, CREATE2
     contract_name: Option<String>,
     contract_address: Option<String>,
     arguments: Option<Vec<String>>,
     transaction: Transaction,
     function: Option<String>,
+    #[serde(default)]
+    additional_contracts: Vec<AdditionalContract>,
+}
+
+/// contract created during the execution of a transaction (by a factory for example)
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all(deserialize = "camelCase", serialize = "camelCase"))]
+pub struct AdditionalContract {
+    transaction_type: String, // CREATE, CREATE2
+    address: String,
+    init_code: Option<String>,
+}
+
+/// the transaction that created a contract and how it was created
+struct CreationTransaction {
+    transaction: TransactionResult,
+    creation: DeploymentCreation,
+    init_code: Option<String>,
+    /// whether the contract is the transaction's own `contractAddress`,
+    /// the only one forge provides the constructor arguments for
+    own_contract: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all(deserialize = "camelCase", serialize = "camelCase"))]
+pub struct Log {
+    address: String,
+    topics: Vec<String>,
+    data: String,
+    block_hash: Option<String>,
+    block_number: Option<String>,
+    transaction_hash: Option<String>,
+    transaction_index: Option<String>,
+    log_index: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all(deserialize = "camelCase", serialize = "camelCase"))]
+pub struct Receipt {
+    transaction_hash: String,
+    transaction_index: String, // example: "0x2"
+    block_hash: String,
+    block_number: String, // example: "0x32bda2"
+    from: String,
+    to: Option<String>,
+    cumulative_gas_used: String,
+    gas_used: String,
+    contract_address: Option<String>,
+    logs: Vec<Log>,
+    status: String, // "0x1" for success, "0x0" for failure
+    logs_bloom: String,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 #[serde(rename_all(deserialize = "camelCase", serialize = "camelCase"))]
 pub struct FileContent {
     transactions: Vec<TransactionResult>,
+    #[serde(default)]
+    receipts: Vec<Receipt>,
+    #[serde(default)]
+    libraries: Vec<String>, // example: "src/Lib.sol:Lib:0x..."
     returns: Value,
+    chain: Option<u64>,
+}
+
+/// content of `multi/<script>-latest/run.json`, one sequence per chain
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all(deserialize = "camelCase", serialize = "camelCase"))]
+pub struct MultiFileContent {
+    deployments: Vec<FileContent>,
+}
+
+/// which broadcast run(s) to read in each script folder
+pub enum BroadcastRun {
+    /// `run-latest.json`
+    Latest,
+    /// `run-<timestamp>.json`
+    Timestamp(String),
+    /// every `run-<timestamp>.json`, replayed in timestamp order
+    All,
+}
+
+/// restrict a sync to some scripts, chains or deployment contexts, an empty filter selects everything
+#[derive(Default)]
+pub struct SyncFilter {
+    pub scripts: Vec<glob::Pattern>,
+    pub chains: Vec<String>,
+    pub contexts: Vec<String>,
+}
+
+impl SyncFilter {
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty() && self.chains.is_empty() && self.contexts.is_empty()
+    }
+
+    /// `script` is the script file name, as used for the broadcast folder (e.g. `Deploy.s.sol`)
+    pub fn matches_script(&self, script: &str) -> bool {
+        self.scripts.is_empty() || self.scripts.iter().any(|pattern| pattern.matches(script))
+    }
+
+    pub fn matches_chain(&self, chain_id: &str) -> bool {
+        self.chains.is_empty() || self.chains.iter().any(|chain| chain.eq(chain_id))
+    }
+
+    pub fn matches_context(&self, context: &str) -> bool {
+        self.contexts.is_empty() || self.contexts.iter().any(|name| name.eq(context))
+    }
+}
+
+/// a deployment recorded by several broadcasts (different scripts or chain folders)
+/// the one from the newest broadcast is kept
+pub struct DeploymentConflict {
+    pub key: String,
+    pub kept_broadcast: String,
+    pub kept_timestamp: u64,
+    pub kept_address: String,
+    pub discarded_broadcast: String,
+    pub discarded_timestamp: u64,
+    pub discarded_address: String,
+}
+
+/// deployment dropped by the script via `ignoreDeployment` and not replaced since
+pub struct IgnoredDeployment {
+    pub name: String,
+    pub deployment_context: String,
+    pub chain_id: String,
+    pub broadcast_file: String,
+}
+
+pub struct BroadcastDeployments {
+    pub deployments: HashMap<String, DeploymentObject>,
+    pub conflicts: Vec<DeploymentConflict>,
+    pub upgrades: Vec<ProxyUpgrade>,
+    /// keyed like `deployments`, by `<context>::<name>`
+    pub ignored: HashMap<String, IgnoredDeployment>,
+}
+
+#[derive(Deserialize)]
+struct BroadcastTimestamp {
+    #[serde(default)]
+    timestamp: u64,
+}
+
+struct BroadcastFile {
+    path: PathBuf,
+    multi: bool,
+    timestamp: u64,
+    data: String,
+}
+
+impl BroadcastFile {
+    fn new(path: PathBuf, multi: bool) -> Result<BroadcastFile, String> {
+        let data = fs::read_to_string(&path)
+            .map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+        let timestamp = from_str::<BroadcastTimestamp>(&data)
+            .map_err(|err| format!("Unable to parse {}: {}", path.display(), err))?
+            .timestamp;
+        Ok(BroadcastFile {
+            path,
+            multi,
+            timestamp,
+            data,
+        })
+    }
 }
 
 pub fn get_last_deployments(
     root_folder: &str,
     broadcast_folder: &str,
-) -> HashMap<String, DeploymentObject> {
-    let re = Regex::new(r"\((.+?)\)").unwrap();
-
+    run: &BroadcastRun,
+    filter: &SyncFilter,
+) -> BroadcastDeployments {
     let folder_path_buf = Path::new(root_folder).join(broadcast_folder);
 
-    let mut new_deployments: HashMap<String, DeploymentObject> = HashMap::new();
+    let mut new_deployments = BroadcastDeployments {
+        deployments: HashMap::new(),
+        conflicts: Vec::new(),
+        upgrades: Vec::new(),
+        ignored: HashMap::new(),
+    };
 
     // return early if directory does not exist
-    if !folder_path_buf.is_dir() { return new_deployments; }
-
-    for script_dir in fs::read_dir(folder_path_buf).unwrap() {
-        match script_dir {
-            Ok(script_dir) => {
-                if script_dir.metadata().unwrap().is_dir() {
-                    // println!("script {}", script_dir.path().display());
-                    for chain_dir in fs::read_dir(script_dir.path()).unwrap() {
-                        match chain_dir {
-                            Ok(chain_dir) => {
-                                if chain_dir.metadata().unwrap().is_dir() {
-                                    // println!("chain: {}", chain_dir.path().display());
-                                    let filepath_buf = chain_dir.path().join("run-latest.json");
-                                    // let filepath = filepath_buf.to_str().unwrap();
-
-                                    if !filepath_buf.is_file() { break; }
-
-                                    let data = fs::read_to_string(filepath_buf)
-                                        .expect("Unable to read file");
-                                    let res: FileContent =
-                                        from_str(&data).expect("Unable to parse");
-                                    let returns = res.returns;
-
-                                    // collect transaction and associate them with contracts
-                                    let mut transaction_per_deployments: HashMap<
-                                        String,
-                                        TransactionResult,
-                                    > = HashMap::new();
-                                    for transaction_result in res.transactions {
-                                        if let Some(contract_address) =
-                                            transaction_result.contract_address.clone()
-                                        {
-                                            transaction_per_deployments.insert(
-                                                contract_address,
-                                                transaction_result.clone(),
-                                            );
-                                        }
-                                        // if transaction_result.transaction_type.eq("CREATE") {
-                                        // }
-                                        // TODO Create2
-                                    }
-
-                                    if let Object(returns) = returns {
-                                        let deployments = returns.get("newDeployments");
-                                        if let Some(deployments) = deployments {
-                                            if deployments["internal_type"]
-                                                == "struct DeployerDeployment[]"
-                                            {
-                                                let value: String =
-                                                    deployments["value"].to_string();
-                                                // println!("{}", value);
-                                                let regex_result = re.captures_iter(value.as_str());
-
-                                                for cap in regex_result {
-                                                    let parts = cap[1].split(", ");
-                                                    let collection = parts.collect::<Vec<&str>>();
-                                                    let name = collection[0];
-                                                    let address = collection[1];
-                                                    let bytecode = collection[2];
-                                                    let args_data = collection[3];
-                                                    let artifact_full_path = collection[4];
-                                                    let deployment_context = collection[5];
-                                                    let chain_id = collection[6];
-
-                                                    // if deployment_context.eq("31337") || deployment_context.eq("1337") {
-                                                    //     // for now we skip on dev network if no specific deployment context were specified
-                                                    //     // this allow `forge test` to not read this by mistake ?
-                                                    //     continue;
-                                                    // }
-
-                                                    if deployment_context.eq("void") {
-                                                        // we do not keep track of the void context
-                                                        continue;
-                                                    }
-
-                                                    let mut artifact_splitted =
-                                                        artifact_full_path.split(":");
-                                                    let artifact_path =
-                                                        artifact_splitted.next().unwrap();
-                                                    let contract_name = artifact_splitted.next();
-
-                                                    if let Some(transaction_result) =
-                                                        transaction_per_deployments.get(address)
-                                                    {
-                                                        let args =
-                                                            transaction_result.arguments.clone();
-                                                        let data = transaction_result
-                                                            .transaction
-                                                            .data
-                                                            .to_string();
-                                                        let tx_hash =
-                                                            transaction_result.hash.to_string();
-
-                                                        // "contractAddress": "0xBEe6FFc1E8627F51CcDF0b4399a1e1abc5165f15",
-                                                        // "function": "upgradeTo(address)",
-                                                        // if let Some(function) = &transaction_result.function {
-                                                        //     if function.eq("upgradeTo(address)") {
-                                                        //         println!("upgrade for {}", name.to_string())
-                                                        //     }
-                                                        // }
-
-                                                        // println!("{}:{}", artifact_path, contract_name.unwrap_or("unknown"));
-                                                        // println!("{} address: {}, artifact_path: {}, contract_name: {}, deployment_context: {}", name, address, artifact_path, contract_name, deployment_context);
-                                                        new_deployments.insert(
-                                                            format!(
-                                                                "{}::{}",
-                                                                deployment_context,
-                                                                name.to_string()
-                                                            ),
-                                                            DeploymentObject {
-                                                                name: name.to_string(),
-                                                                address: address.to_string(),
-                                                                bytecode: bytecode.to_string(),
-                                                                args_data: args_data.to_string(),
-                                                                tx_hash: tx_hash,
-                                                                args: args,
-                                                                data: data,
-                                                                contract_name: contract_name
-                                                                    .map(|s| s.to_string()),
-                                                                artifact_path: artifact_path
-                                                                    .to_string(),
-                                                                deployment_context:
-                                                                    deployment_context.to_string(),
-                                                                chain_id: chain_id.to_string(),
-                                                            },
-                                                        );
-                                                    } else {
-                                                        eprintln!("could not find tx for in-memory deployed contract {} at {}", name, address);
-                                                    }
-                                                }
-                                            } else {
-                                                println!("not matching returns type")
-                                            }
-                                        } else {
-                                            // println!("no deployments")
-                                        }
-                                    }
-                                }
+    if !folder_path_buf.is_dir() {
+        return new_deployments;
+    }
+
+    let mut broadcast_files: Vec<BroadcastFile> = Vec::new();
+    // old runs could have been produced by an incompatible version of forge or of the Deployer
+    let mut add_broadcast_file = |path: PathBuf, multi: bool| match BroadcastFile::new(path, multi)
+    {
+        Ok(broadcast_file) => broadcast_files.push(broadcast_file),
+        Err(err) => skip_run(run, err),
+    };
+    for script_dir in fs::read_dir(folder_path_buf).unwrap().flatten() {
+        if script_dir.metadata().unwrap().is_dir() {
+            // println!("script {}", script_dir.path().display());
+            let multi = script_dir.file_name().eq("multi");
+            if !multi && !filter.matches_script(&script_dir.file_name().to_string_lossy()) {
+                continue;
+            }
+            for chain_dir in fs::read_dir(script_dir.path()).unwrap().flatten() {
+                if chain_dir.metadata().unwrap().is_dir() {
+                    // println!("chain: {}", chain_dir.path().display());
+                    if multi {
+                        // multi-chain broadcasts are saved in `multi/<script>-<latest|timestamp>/run.json`
+                        let dir_name = chain_dir.file_name().to_string_lossy().to_string();
+                        let (script, suffix) = match dir_name.rsplit_once('-') {
+                            Some((script, suffix)) => (script.to_string(), suffix.to_string()),
+                            None => continue,
+                        };
+                        if filter.matches_script(&script) && is_selected(run, &suffix) {
+                            let filepath_buf = chain_dir.path().join("run.json");
+                            if filepath_buf.is_file() {
+                                add_broadcast_file(filepath_buf, multi);
+                            }
+                        }
+                    } else {
+                        // single chain broadcasts are saved in `<script>/<chainId>/run-<latest|timestamp>.json`
+                        if !filter.matches_chain(&chain_dir.file_name().to_string_lossy()) {
+                            continue;
+                        }
+                        for run_file in fs::read_dir(chain_dir.path()).unwrap().flatten() {
+                            let file_name = run_file.file_name().to_string_lossy().to_string();
+                            let suffix = match file_name
+                                .strip_prefix("run-")
+                                .and_then(|name| name.strip_suffix(".json"))
+                            {
+                                Some(suffix) => suffix.to_string(),
+                                None => continue,
+                            };
+                            if is_selected(run, &suffix) {
+                                add_broadcast_file(run_file.path(), multi);
                             }
-                            Err(_) => (),
                         }
                     }
                 }
             }
-            Err(_) => (),
         }
     }
 
-    return new_deployments;
+    if let BroadcastRun::Timestamp(timestamp) = run {
+        if broadcast_files.is_empty() {
+            panic!("no broadcast found for run {}", timestamp);
+        }
+    }
+
+    // older broadcasts first so that newer deployments replace them
+    // the path is used to order broadcasts with the same timestamp so the result does not depend on the file system
+    broadcast_files.sort_by(|a, b| {
+        a.timestamp
+            .cmp(&b.timestamp)
+            .then_with(|| a.path.cmp(&b.path))
+    });
+
+    for broadcast_file in broadcast_files {
+        let result = if broadcast_file.multi {
+            collect_multi_deployments(&broadcast_file, filter, &mut new_deployments)
+        } else {
+            collect_deployments(&broadcast_file, filter, &mut new_deployments)
+        };
+        if let Err(err) = result {
+            skip_run(run, err);
+        }
+    }
+
+    new_deployments
+}
+
+/// when replaying every run, the runs that cannot be read are skipped, otherwise the error is fatal
+fn skip_run(run: &BroadcastRun, err: String) {
+    if let BroadcastRun::All = run {
+        eprintln!("skipping run: {}", err);
+    } else {
+        panic!("{}", err);
+    }
+}
+
+/// whether the run is selected, `suffix` being either `latest` or the run's timestamp
+fn is_selected(run: &BroadcastRun, suffix: &str) -> bool {
+    match run {
+        BroadcastRun::Latest => suffix.eq("latest"),
+        BroadcastRun::Timestamp(timestamp) => suffix.eq(timestamp),
+        BroadcastRun::All => suffix.parse::<u64>().is_ok(),
+    }
+}
+
+/// the folder a broadcast belongs to, runs of the same script on the same chain share it
+fn broadcast_source(path: &Path) -> PathBuf {
+    let parent = path.parent().unwrap_or(path);
+    let multi = parent
+        .parent()
+        .map(|folder| folder.ends_with("multi"))
+        .unwrap_or(false);
+    match parent.file_name().map(|name| name.to_string_lossy()) {
+        Some(name) if multi => match name.rsplit_once('-') {
+            Some((script, _)) => parent.with_file_name(script),
+            None => parent.to_path_buf(),
+        },
+        _ => parent.to_path_buf(),
+    }
+}
+
+pub fn report_conflicts(conflicts: &[DeploymentConflict]) {
+    for conflict in conflicts {
+        println!(
+            "conflict for {}: kept {} from {} (timestamp {}), discarded {} from {} (timestamp {})",
+            conflict.key,
+            conflict.kept_address,
+            conflict.kept_broadcast,
+            conflict.kept_timestamp,
+            conflict.discarded_address,
+            conflict.discarded_broadcast,
+            conflict.discarded_timestamp
+        );
+    }
+}
+
+fn collect_deployments(
+    broadcast_file: &BroadcastFile,
+    filter: &SyncFilter,
+    new_deployments: &mut BroadcastDeployments,
+) -> Result<(), String> {
+    let res: FileContent = from_str(&broadcast_file.data)
+        .map_err(|err| format!("Unable to parse {}: {}", broadcast_file.path.display(), err))?;
+
+    let (deployer_deployments, ignored_deployments) =
+        split_ignored_deployments(decode_deployments(&broadcast_file.path, &res.returns)?);
+    let artifacts_snapshot = decode_artifacts_snapshot(&res.returns);
+    let sequence = ChainSequence::new(res).map_err(|err| invalid_run(broadcast_file, err))?;
+    let broadcast_deployments = resolve_deployments(
+        deployer_deployments,
+        std::slice::from_ref(&sequence),
+        broadcast_file,
+        artifacts_snapshot,
+        filter,
+    )
+    .map_err(|err| invalid_run(broadcast_file, err))?;
+    new_deployments
+        .upgrades
+        .extend(sequence.upgrades_matching(filter).cloned());
+    record_ignored_deployments(ignored_deployments, broadcast_file, filter, new_deployments);
+    record_deployments(broadcast_deployments, broadcast_file, new_deployments);
+    Ok(())
+}
+
+fn collect_multi_deployments(
+    broadcast_file: &BroadcastFile,
+    filter: &SyncFilter,
+    new_deployments: &mut BroadcastDeployments,
+) -> Result<(), String> {
+    let res: MultiFileContent = from_str(&broadcast_file.data)
+        .map_err(|err| format!("Unable to parse {}: {}", broadcast_file.path.display(), err))?;
+
+    // the script returns are the same for every chain, we only decode them once
+    let mut deployer_deployments = Vec::new();
+    let mut artifacts_snapshot = None;
+    for sequence in &res.deployments {
+        if sequence.returns.get("newDeployments").is_some() {
+            deployer_deployments = decode_deployments(&broadcast_file.path, &sequence.returns)?;
+            artifacts_snapshot = decode_artifacts_snapshot(&sequence.returns);
+            break;
+        }
+    }
+    let (deployer_deployments, ignored_deployments) =
+        split_ignored_deployments(deployer_deployments);
+    let sequences = res
+        .deployments
+        .into_iter()
+        .map(ChainSequence::new)
+        .collect::<Result<Vec<ChainSequence>, String>>()
+        .map_err(|err| invalid_run(broadcast_file, err))?;
+    let broadcast_deployments = resolve_deployments(
+        deployer_deployments,
+        &sequences,
+        broadcast_file,
+        artifacts_snapshot,
+        filter,
+    )
+    .map_err(|err| invalid_run(broadcast_file, err))?;
+    for sequence in &sequences {
+        new_deployments
+            .upgrades
+            .extend(sequence.upgrades_matching(filter).cloned());
+    }
+    record_ignored_deployments(ignored_deployments, broadcast_file, filter, new_deployments);
+    record_deployments(broadcast_deployments, broadcast_file, new_deployments);
+    Ok(())
+}
+
+fn invalid_run(broadcast_file: &BroadcastFile, err: String) -> String {
+    format!("{} in {}", err, broadcast_file.path.display())
+}
+
+/// the deployments returned by the script's `run()` as `newDeployments`
+fn decode_deployments(filepath: &Path, returns: &Value) -> Result<Vec<DeployerDeployment>, String> {
+    let deployments = match returns.get("newDeployments") {
+        Some(deployments) => deployments,
+        None => {
+            // println!("no deployments")
+            return Ok(Vec::new());
+        }
+    };
+    if deployments["internal_type"] != "struct DeployerDeployment[]" {
+        println!("not matching returns type");
+        return Ok(Vec::new());
+    }
+    let value = deployments["value"].as_str().ok_or_else(|| {
+        format!(
+            "newDeployments value is not a string in {}",
+            filepath.display()
+        )
+    })?;
+    parse_deployer_deployments(value).map_err(|err| {
+        format!(
+            "failed to decode newDeployments in {}: {}",
+            filepath.display(),
+            err
+        )
+    })
+}
+
+/// the folder where the script saved the artifacts used during its run, returned as `artifactsSnapshot`
+fn decode_artifacts_snapshot(returns: &Value) -> Option<String> {
+    let snapshot = returns.get("artifactsSnapshot")?;
+    if snapshot["internal_type"] != "string" {
+        return None;
+    }
+    let path = snapshot["value"].as_str()?.trim_matches('"');
+    if path.is_empty() {
+        None
+    } else {
+        Some(path.to_string())
+    }
+}
+
+/// transactions and receipts broadcasted on a single chain
+struct ChainSequence {
+    chain_id: Option<String>,
+    transaction_per_deployments: HashMap<String, CreationTransaction>,
+    receipt_per_transactions: HashMap<String, Receipt>,
+    upgrades: Vec<ProxyUpgrade>,
+    libraries: BTreeMap<String, String>,
+}
+
+impl ChainSequence {
+    fn new(res: FileContent) -> Result<ChainSequence, String> {
+        let chain_id = res.chain.map(|chain| chain.to_string());
+        let receipt_per_transactions: HashMap<String, Receipt> = res
+            .receipts
+            .into_iter()
+            .map(|receipt| (receipt.transaction_hash.to_lowercase(), receipt))
+            .collect();
+
+        // collect transaction and associate them with contracts
+        let mut transaction_per_deployments: HashMap<String, CreationTransaction> = HashMap::new();
+        let mut upgrades: Vec<ProxyUpgrade> = Vec::new();
+        for transaction_result in res.transactions {
+            let tx_hash = match &transaction_result.hash {
+                Some(hash) => hash.to_string(),
+                // the transaction was not broadcasted
+                None => continue,
+            };
+
+            let receipt = receipt_per_transactions.get(&tx_hash.to_lowercase());
+            let succeeded = match receipt {
+                Some(receipt) => hex_to_u64(&receipt.status)? != 0,
+                None => true,
+            };
+            if succeeded {
+                for (proxy, implementation) in find_upgrades(&transaction_result, receipt) {
+                    upgrades.push(ProxyUpgrade {
+                        chain_id: chain_id.clone(),
+                        proxy,
+                        implementation,
+                        tx_hash: tx_hash.to_string(),
+                        block_number: receipt
+                            .map(|receipt| hex_to_u64(&receipt.block_number))
+                            .transpose()?,
+                    });
+                }
+            }
+
+            // contracts created by a factory (or by the constructor of a created contract)
+            let factory = if transaction_result.transaction_type.eq("CREATE") {
+                transaction_result.contract_address.clone()
+            } else {
+                transaction_result.transaction.to.clone()
+            };
+            for additional_contract in &transaction_result.additional_contracts {
+                transaction_per_deployments.insert(
+                    additional_contract.address.to_lowercase(),
+                    CreationTransaction {
+                        transaction: transaction_result.clone(),
+                        creation: DeploymentCreation {
+                            method: additional_contract.transaction_type.to_string(),
+                            factory: factory.clone(),
+                        },
+                        init_code: additional_contract.init_code.clone(),
+                        own_contract: false,
+                    },
+                );
+            }
+
+            if let Some(contract_address) = transaction_result.contract_address.clone() {
+                if transaction_result.transaction_type.eq("CREATE") {
+                    transaction_per_deployments.insert(
+                        contract_address.to_lowercase(),
+                        CreationTransaction {
+                            creation: DeploymentCreation {
+                                method: "CREATE".to_string(),
+                                factory: None,
+                            },
+                            init_code: Some(transaction_result.transaction.data.to_string()),
+                            own_contract: true,
+                            transaction: transaction_result,
+                        },
+                    );
+                } else if transaction_result.transaction_type.eq("CREATE2") {
+                    // CREATE2 transactions are sent to a deterministic deployer with the salt prepended to the init code
+                    let data = &transaction_result.transaction.data;
+                    let init_code = if data.len() >= 2 + 64 {
+                        Some(format!("0x{}", &data[2 + 64..]))
+                    } else {
+                        None
+                    };
+                    transaction_per_deployments.insert(
+                        contract_address.to_lowercase(),
+                        CreationTransaction {
+                            creation: DeploymentCreation {
+                                method: "CREATE2".to_string(),
+                                factory: transaction_result.transaction.to.clone(),
+                            },
+                            init_code,
+                            own_contract: true,
+                            transaction: transaction_result,
+                        },
+                    );
+                }
+            }
+        }
+
+        // libraries deployed by forge to link the contracts, keyed by `<path>:<name>`
+        let libraries = res
+            .libraries
+            .iter()
+            .filter_map(|library| {
+                library
+                    .rsplit_once(':')
+                    .map(|(id, address)| (id.to_string(), address.to_string()))
+            })
+            .collect::<BTreeMap<String, String>>();
+
+        Ok(ChainSequence {
+            chain_id,
+            transaction_per_deployments,
+            receipt_per_transactions,
+            upgrades,
+            libraries,
+        })
+    }
+
+    /// upgrades of the sequence on the chains selected by the filter
+    fn upgrades_matching<'a>(
+        &'a self,
+        filter: &'a SyncFilter,
+    ) -> impl Iterator<Item = &'a ProxyUpgrade> + 'a {
+        self.upgrades.iter().filter(|upgrade| {
+            upgrade
+                .chain_id
+                .as_deref()
+                .map(|chain_id| filter.matches_chain(chain_id))
+                .unwrap_or(true)
+        })
+    }
+}
+
+// keccak256("Upgraded(address)"), emitted by ERC-1967 proxies
+pub const UPGRADED_EVENT_TOPIC: &str =
+    "0xbc7cd75a20ee27fd9adebab32041f755214dbc6bffa90cc0225b39da2e5c2d3b";
+// upgradeTo(address)
+const UPGRADE_TO_SELECTOR: &str = "0x3659cfe6";
+// upgradeToAndCall(address,bytes)
+const UPGRADE_TO_AND_CALL_SELECTOR: &str = "0x4f1ef286";
+
+/// find the (proxy, implementation) pairs upgraded by a transaction
+/// from its `Upgraded` logs and from `upgradeTo` / `upgradeToAndCall` calls
+fn find_upgrades(
+    transaction_result: &TransactionResult,
+    receipt: Option<&Receipt>,
+) -> Vec<(String, String)> {
+    let mut upgrades: Vec<(String, String)> = Vec::new();
+
+    if let Some(receipt) = receipt {
+        for log in &receipt.logs {
+            if log.topics.len() == 2
+                && log.topics[0].eq_ignore_ascii_case(UPGRADED_EVENT_TOPIC)
+                && log.topics[1].len() >= 40
+            {
+                let implementation = format!("0x{}", &log.topics[1][log.topics[1].len() - 40..]);
+                upgrades.push((log.address.to_lowercase(), implementation.to_lowercase()));
+            }
+        }
+    }
+
+    let data = transaction_result.transaction.data.to_lowercase();
+    if data.starts_with(UPGRADE_TO_SELECTOR) || data.starts_with(UPGRADE_TO_AND_CALL_SELECTOR) {
+        if let (Some(proxy), Some(word)) = (&transaction_result.transaction.to, data.get(10..74)) {
+            let upgrade = (proxy.to_lowercase(), format!("0x{}", &word[24..]));
+            if !upgrades.contains(&upgrade) {
+                upgrades.push(upgrade);
+            }
+        }
+    }
+
+    upgrades
+}
+
+/// `ignoreDeployment` records the name with the zero address among the new deployments
+/// only the last entry of a name matters: an ignore followed by a new deployment is a replacement,
+/// while a deployment followed by an ignore is dropped
+fn split_ignored_deployments(
+    deployer_deployments: Vec<DeployerDeployment>,
+) -> (Vec<DeployerDeployment>, Vec<DeployerDeployment>) {
+    let is_ignore = |deployment: &DeployerDeployment| {
+        deployment
+            .addr
+            .trim_start_matches("0x")
+            .chars()
+            .all(|c| c == '0')
+    };
+    let key = |deployment: &DeployerDeployment| {
+        format!("{}::{}", deployment.deployment_context, deployment.name)
+    };
+    let mut last_is_ignore: HashMap<String, bool> = HashMap::new();
+    for deployment in &deployer_deployments {
+        last_is_ignore.insert(key(deployment), is_ignore(deployment));
+    }
+    let (ignored, deployments): (Vec<DeployerDeployment>, Vec<DeployerDeployment>) =
+        deployer_deployments
+            .into_iter()
+            .filter(|deployment| last_is_ignore[&key(deployment)] == is_ignore(deployment))
+            .partition(is_ignore);
+    (deployments, ignored)
+}
+
+/// ignored deployments drop the deployment recorded by previous broadcasts
+/// the deployments of the same broadcast are recorded after and thus replace them
+fn record_ignored_deployments(
+    ignored_deployments: Vec<DeployerDeployment>,
+    broadcast_file: &BroadcastFile,
+    filter: &SyncFilter,
+    new_deployments: &mut BroadcastDeployments,
+) {
+    for ignored in ignored_deployments {
+        if ignored.deployment_context.eq("void")
+            || !filter.matches_context(&ignored.deployment_context)
+            || !filter.matches_chain(&ignored.chain_id_as_string)
+        {
+            continue;
+        }
+        let key = format!("{}::{}", ignored.deployment_context, ignored.name);
+        new_deployments.deployments.remove(&key);
+        new_deployments.ignored.insert(
+            key,
+            IgnoredDeployment {
+                name: ignored.name,
+                deployment_context: ignored.deployment_context,
+                chain_id: ignored.chain_id_as_string,
+                broadcast_file: broadcast_file.path.display().to_string(),
+            },
+        );
+    }
+}
+
+/// match the deployments returned by the script with their creation transaction
+/// nothing is recorded here so that an invalid run leaves the collected deployments untouched
+fn resolve_deployments(
+    deployer_deployments: Vec<DeployerDeployment>,
+    sequences: &[ChainSequence],
+    broadcast_file: &BroadcastFile,
+    artifacts_snapshot: Option<String>,
+    filter: &SyncFilter,
+) -> Result<HashMap<String, DeploymentObject>, String> {
+    // a broadcast can record the same name more than once, the last one is the one kept
+    let mut broadcast_deployments: HashMap<String, DeploymentObject> = HashMap::new();
+    for deployment in deployer_deployments {
+        // if deployment.deployment_context.eq("31337") || deployment.deployment_context.eq("1337") {
+        //     // for now we skip on dev network if no specific deployment context were specified
+        //     // this allow `forge test` to not read this by mistake ?
+        //     continue;
+        // }
+
+        if deployment.deployment_context.eq("void") {
+            // we do not keep track of the void context
+            continue;
+        }
+
+        if !filter.matches_context(&deployment.deployment_context) {
+            continue;
+        }
+
+        let mut artifact_splitted = deployment.artifact.split(':');
+        let artifact_path = artifact_splitted.next().unwrap();
+        let contract_name = artifact_splitted.next();
+
+        // the deployment is only looked up on the chain it was recorded for (or on sequences without chain id)
+        // CREATE2 addresses being the same on every chain, another chain's transaction could match it
+        let address = deployment.addr.to_lowercase();
+        let found = sequences
+            .iter()
+            .filter(|sequence| match sequence.chain_id.as_deref() {
+                Some(chain_id) => chain_id == deployment.chain_id_as_string,
+                None => true,
+            })
+            .find_map(|sequence| {
+                sequence
+                    .transaction_per_deployments
+                    .get(&address)
+                    .map(|creation_transaction| (sequence, creation_transaction))
+            });
+
+        if let Some((sequence, creation_transaction)) = found {
+            let chain_id = deployment.chain_id_as_string.to_string();
+            if !filter.matches_chain(&chain_id) {
+                continue;
+            }
+            let transaction_result = &creation_transaction.transaction;
+            // arguments are only provided by forge for the contract created by the transaction itself
+            let args = if creation_transaction.own_contract {
+                transaction_result.arguments.clone()
+            } else {
+                None
+            };
+            let data = creation_transaction
+                .init_code
+                .clone()
+                .unwrap_or_else(|| format!("{}{}", deployment.bytecode, &deployment.args[2..]));
+            let tx_hash = transaction_result.hash.clone().unwrap_or_default();
+
+            let receipt = sequence
+                .receipt_per_transactions
+                .get(&tx_hash.to_lowercase());
+            if let Some(receipt) = receipt {
+                if hex_to_u64(&receipt.status)? == 0 {
+                    eprintln!(
+                        "transaction {} deploying {} at {} failed (status: {}), it will not be recorded",
+                        tx_hash, deployment.name, deployment.addr, receipt.status
+                    );
+                    continue;
+                }
+            }
+
+            broadcast_deployments.insert(
+                format!("{}::{}", deployment.deployment_context, deployment.name),
+                DeploymentObject {
+                    name: deployment.name.to_string(),
+                    address: deployment.addr.to_string(),
+                    bytecode: deployment.bytecode.to_string(),
+                    args_data: deployment.args.to_string(),
+                    tx_hash,
+                    args,
+                    data,
+                    contract_name: contract_name.map(|s| s.to_string()),
+                    artifact_path: artifact_path.to_string(),
+                    deployment_context: deployment.deployment_context.to_string(),
+                    chain_id,
+                    receipt: receipt.map(to_deployment_receipt).transpose()?,
+                    creation: creation_transaction.creation.clone(),
+                    libraries: sequence.libraries.clone(),
+                    broadcast_file: broadcast_file.path.display().to_string(),
+                    timestamp: broadcast_file.timestamp,
+                    artifacts_snapshot: artifacts_snapshot.clone(),
+                },
+            );
+        } else if deployment.artifact.is_empty() {
+            // proxied deployments (saved without artifact by forge-deploy-proxy) are not created when upgraded
+            // their `_Proxy` and `_Implementation` deployments provide the details
+            if !filter.matches_chain(&deployment.chain_id_as_string) {
+                continue;
+            }
+            broadcast_deployments.insert(
+                format!("{}::{}", deployment.deployment_context, deployment.name),
+                DeploymentObject {
+                    name: deployment.name.to_string(),
+                    address: deployment.addr.to_string(),
+                    bytecode: deployment.bytecode.to_string(),
+                    args_data: deployment.args.to_string(),
+                    tx_hash: String::new(),
+                    args: None,
+                    data: String::new(),
+                    contract_name: None,
+                    artifact_path: String::new(),
+                    deployment_context: deployment.deployment_context.to_string(),
+                    chain_id: deployment.chain_id_as_string.to_string(),
+                    receipt: None,
+                    creation: DeploymentCreation::default(),
+                    libraries: BTreeMap::new(),
+                    broadcast_file: broadcast_file.path.display().to_string(),
+                    timestamp: broadcast_file.timestamp,
+                    artifacts_snapshot: artifacts_snapshot.clone(),
+                },
+            );
+        } else {
+            eprintln!(
+                "could not find tx for in-memory deployed contract {} at {}",
+                deployment.name, deployment.addr
+            );
+        }
+    }
+    Ok(broadcast_deployments)
+}
+
+fn record_deployments(
+    mut broadcast_deployments: HashMap<String, DeploymentObject>,
+    broadcast_file: &BroadcastFile,
+    new_deployments: &mut BroadcastDeployments,
+) {
+    let source = broadcast_source(&broadcast_file.path);
+    let mut keys = broadcast_deployments
+        .keys()
+        .cloned()
+        .collect::<Vec<String>>();
+    keys.sort();
+    for key in keys {
+        let deployment = broadcast_deployments.remove(&key).unwrap();
+        if let Some(existing) = new_deployments.deployments.get(&key) {
+            if broadcast_source(Path::new(&existing.broadcast_file)) != source
+                && !existing.address.eq_ignore_ascii_case(&deployment.address)
+            {
+                new_deployments.conflicts.push(DeploymentConflict {
+                    key: key.to_string(),
+                    kept_broadcast: deployment.broadcast_file.to_string(),
+                    kept_timestamp: deployment.timestamp,
+                    kept_address: deployment.address.to_string(),
+                    discarded_broadcast: existing.broadcast_file.to_string(),
+                    discarded_timestamp: existing.timestamp,
+                    discarded_address: existing.address.to_string(),
+                });
+            }
+        }
+        new_deployments.ignored.remove(&key);
+        new_deployments.deployments.insert(key, deployment);
+    }
+}
+
+fn hex_to_u64(value: &str) -> Result<u64, String> {
+    u64::from_str_radix(value.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("invalid hex number: {}", value))
+}
+
+/// convert forge's receipt (hex encoded quantities) to hardhat-deploy's receipt layout
+fn to_deployment_receipt(receipt: &Receipt) -> Result<DeploymentReceipt, String> {
+    Ok(DeploymentReceipt {
+        to: receipt.to.clone(),
+        from: receipt.from.to_string(),
+        contract_address: receipt.contract_address.clone(),
+        transaction_index: hex_to_u64(&receipt.transaction_index)?,
+        gas_used: hex_to_u64(&receipt.gas_used)?.to_string(),
+        logs_bloom: receipt.logs_bloom.to_string(),
+        block_hash: receipt.block_hash.to_string(),
+        transaction_hash: receipt.transaction_hash.to_string(),
+        logs: receipt
+            .logs
+            .iter()
+            .map(|log| {
+                Ok(DeploymentLog {
+                    transaction_index: hex_to_u64(
+                        log.transaction_index
+                            .as_deref()
+                            .unwrap_or(&receipt.transaction_index),
+                    )?,
+                    block_number: hex_to_u64(
+                        log.block_number.as_deref().unwrap_or(&receipt.block_number),
+                    )?,
+                    transaction_hash: log
+                        .transaction_hash
+                        .clone()
+                        .unwrap_or_else(|| receipt.transaction_hash.to_string()),
+                    address: log.address.to_string(),
+                    topics: log.topics.clone(),
+                    data: log.data.to_string(),
+                    log_index: log
+                        .log_index
+                        .as_deref()
+                        .map(hex_to_u64)
+                        .transpose()?
+                        .unwrap_or(0),
+                    block_hash: log
+                        .block_hash
+                        .clone()
+                        .unwrap_or_else(|| receipt.block_hash.to_string()),
+                })
+            })
+            .collect::<Result<Vec<DeploymentLog>, String>>()?,
+        block_number: hex_to_u64(&receipt.block_number)?,
+        cumulative_gas_used: hex_to_u64(&receipt.cumulative_gas_used)?.to_string(),
+        status: hex_to_u64(&receipt.status)?,
+        byzantium: true,
+    })
 }
@@ -1,4 +1,4 @@
-use std::{fs, path::Path};
+use std::{collections::BTreeMap, fs, path::Path};
 
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
@@ -20,11 +20,77 @@ pub struct ContextDeployments {
     pub contracts: Map<String, Value>,
 }
 
+/// ABI entries to keep in the export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AbiFilter {
+    /// drop the constructor
+    NoConstructor,
+    /// drop custom errors
+    NoErrors,
+    /// keep events, combined with the other `*-only` filters
+    EventsOnly,
+    /// keep view and pure functions, combined with the other `*-only` filters
+    ViewsOnly,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    pub include_args: bool,
+    /// only export the contracts whose name matches one of these, all if empty
+    pub include: Vec<glob::Pattern>,
+    /// skip the contracts whose name matches one of these
+    pub exclude: Vec<glob::Pattern>,
+    pub abi_filters: Vec<AbiFilter>,
+}
+
+impl ExportOptions {
+    fn matches_name(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|pattern| pattern.matches(name)))
+            && !self.exclude.iter().any(|pattern| pattern.matches(name))
+    }
+
+    fn keeps_abi_entry(&self, entry: &Value) -> bool {
+        let entry_type = entry
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or("function");
+        let only_filters: Vec<&AbiFilter> = self
+            .abi_filters
+            .iter()
+            .filter(|filter| matches!(filter, AbiFilter::EventsOnly | AbiFilter::ViewsOnly))
+            .collect();
+        if !only_filters.is_empty()
+            && !only_filters.iter().any(|filter| match filter {
+                AbiFilter::EventsOnly => entry_type == "event",
+                _ => entry_type == "function" && is_view(entry),
+            })
+        {
+            return false;
+        }
+        !self.abi_filters.iter().any(|filter| match filter {
+            AbiFilter::NoConstructor => entry_type == "constructor",
+            AbiFilter::NoErrors => entry_type == "error",
+            _ => false,
+        })
+    }
+}
+
+/// functions that do not modify the state, older ABIs only have the `constant` flag
+fn is_view(entry: &Value) -> bool {
+    match entry.get("stateMutability").and_then(Value::as_str) {
+        Some(mutability) => mutability == "view" || mutability == "pure",
+        None => entry
+            .get("constant")
+            .and_then(Value::as_bool)
+            .unwrap_or(false),
+    }
+}
+
 pub fn get_deployments(
     root_folder: &str,
     deployments_folder: &str,
     deployment_context: &str,
-    include_args: bool,
+    options: &ExportOptions,
 ) -> ContextDeployments {
     let mut deployments = Map::new();
 
@@ -37,54 +103,90 @@ pub fn get_deployments(
 
     let mut chain_id: String = String::new();
     if let Ok(dir) = fs::read_dir(folder_path) {
-        for json_filepath_result in dir {
-            match json_filepath_result {
-                Ok(json_file_entry) => {
-                    let json_filename = json_file_entry.file_name();
-                    let filename = json_filename.to_str().unwrap();
-                    if filename.ends_with(".json") {
-                        let deployment_name = filename.strip_suffix(".json").unwrap();
-                        let data = fs::read_to_string(json_file_entry.path())
-                            .expect("Unable to read file");
-                        let res: DeploymentJSON =
-                            serde_json::from_str(&data).expect("Unable to parse");
-                        let mut object = Map::new();
-                        object.insert("address".to_string(), Value::String(res.address));
-                        object.insert("abi".to_string(), Value::Array(res.abi));
-                        object.insert("tx_hash".to_string(), Value::String(res.tx_hash));
-                        if include_args {
-                            if let Some(args) = res.args {
-                                let values = args
-                                    .iter()
-                                    .map(|v| serde_json::to_value(v).expect("failed to convert"))
-                                    .collect();
-                                object.insert("args".to_string(), Value::Array(values));
-                            }
-                        }
-
-                        // object.insert("blockNumber".to_string(), Value::Array(res.abi));
-                        // object.insert("blockTimestamp".to_string(), Value::Array(res.abi));
-                        // object.insert("args".to_string(), Value::Array(res.abi));
-                        deployments.insert(deployment_name.to_string(), Value::Object(object));
-                    } else if filename.eq(".chainId") {
-                        chain_id = fs::read_to_string(json_file_entry.path())
-                            .expect("Unable to read file");
+        for json_file_entry in dir.flatten() {
+            let json_filename = json_file_entry.file_name();
+            let filename = json_filename.to_str().unwrap();
+            if filename.ends_with(".json") {
+                let deployment_name = filename.strip_suffix(".json").unwrap();
+                if !options.matches_name(deployment_name) {
+                    continue;
+                }
+                let data = fs::read_to_string(json_file_entry.path()).expect("Unable to read file");
+                let res: DeploymentJSON = serde_json::from_str(&data).expect("Unable to parse");
+                let mut object = Map::new();
+                object.insert("address".to_string(), Value::String(res.address));
+                let abi = res
+                    .abi
+                    .into_iter()
+                    .filter(|entry| options.keeps_abi_entry(entry))
+                    .collect();
+                object.insert("abi".to_string(), Value::Array(abi));
+                object.insert("tx_hash".to_string(), Value::String(res.tx_hash));
+                if options.include_args {
+                    if let Some(args) = res.args {
+                        object.insert("args".to_string(), Value::Array(args));
                     }
                 }
-                Err(_) => (),
+
+                // object.insert("blockNumber".to_string(), Value::Array(res.abi));
+                // object.insert("blockTimestamp".to_string(), Value::Array(res.abi));
+                // object.insert("args".to_string(), Value::Array(res.abi));
+                deployments.insert(deployment_name.to_string(), Value::Object(object));
+            } else if filename.eq(".chainId") {
+                chain_id = fs::read_to_string(json_file_entry.path()).expect("Unable to read file");
             }
         }
     }
 
-    let context_deployments = ContextDeployments {
+    ContextDeployments {
         name: deployment_context.to_string(),
-        chain_id: chain_id,
+        chain_id,
         contracts: deployments,
-    };
-    return context_deployments;
+    }
 }
 
-pub fn export_minimal_deployments(deployments: &ContextDeployments, out: Vec<&str>) {
+/// deployments of every context matching one of the patterns, keyed by chain id then context name
+pub fn get_all_deployments(
+    root_folder: &str,
+    deployments_folder: &str,
+    patterns: &[glob::Pattern],
+    options: &ExportOptions,
+) -> BTreeMap<String, BTreeMap<String, ContextDeployments>> {
+    let mut all_deployments: BTreeMap<String, BTreeMap<String, ContextDeployments>> =
+        BTreeMap::new();
+
+    let folder_path_buf = Path::new(root_folder).join(deployments_folder);
+    if let Ok(dir) = fs::read_dir(&folder_path_buf) {
+        for context_entry in dir.flatten() {
+            let context_filename = context_entry.file_name();
+            let deployment_context = context_filename.to_str().unwrap();
+            if deployment_context.starts_with('.')
+                || !context_entry.path().is_dir()
+                || !patterns
+                    .iter()
+                    .any(|pattern| pattern.matches(deployment_context))
+            {
+                continue;
+            }
+            let deployments =
+                get_deployments(root_folder, deployments_folder, deployment_context, options);
+            if deployments.chain_id.is_empty() {
+                eprintln!(
+                    "{} has no .chainId file, it will not be exported",
+                    deployment_context
+                );
+                continue;
+            }
+            all_deployments
+                .entry(deployments.chain_id.trim().to_string())
+                .or_default()
+                .insert(deployment_context.to_string(), deployments);
+        }
+    }
+    all_deployments
+}
+
+pub fn export_minimal_deployments<T: Serialize>(deployments: &T, out: Vec<&str>) {
     // let mut object = Map::new();
     // object.insert("name".to_string(), Value::String(depoyment_context.to_string()));
     // object.insert("chainId".to_string(), Value::String(deployment_chainid.to_string()));
@@ -92,20 +194,176 @@ pub fn export_minimal_deployments(deployments: &ContextDeployments, out: Vec<&st
 
     let data = serde_json::to_string_pretty(deployments).expect("Failed to stringify");
     let data_as_typescript = format!("export default {} as const;", data);
-    // TODO js
-    // let data_as_javascript = format!("export default {} as const;", data);
+    let data_as_javascript = format!("export default {};", data);
+    let data_as_commonjs = format!("module.exports = {};", data);
+    let data_as_declaration = format!(
+        "declare const deployments: {};\nexport default deployments;",
+        typescript_type(
+            &serde_json::to_value(deployments).expect("Failed to convert"),
+            0
+        )
+    );
     for output in out {
         if let Some(parent) = Path::new(output).parent() {
             fs::create_dir_all(parent).expect("create folder");
         }
 
-        if output.ends_with(".ts") {
+        if output.ends_with(".d.ts") {
+            fs::write(output, &data_as_declaration).expect("failed to write file");
+        } else if output.ends_with(".ts") {
             fs::write(output, &data_as_typescript).expect("failed to write file");
-        // TODO js
-        // } else if (output.ends_with(".js")) {
-        //     fs::write(output, &data_as_javascript).expect("failed to write file");
+        } else if output.ends_with(".js") || output.ends_with(".mjs") {
+            fs::write(output, &data_as_javascript).expect("failed to write file");
+        } else if output.ends_with(".cjs") {
+            fs::write(output, &data_as_commonjs).expect("failed to write file");
         } else {
             fs::write(output, &data).expect("failed to write file");
         }
     }
 }
+
+/// write a `<Name>.ts` module per contract exporting its `address`, `abi` and `chainId` as constants,
+/// and an `index.ts` re-exporting each of them under the contract name
+pub fn export_typescript_modules(deployments: &ContextDeployments, folder: &Path) {
+    fs::create_dir_all(folder).expect("create folder");
+
+    // viem expects a number, the chain id is kept as a string only if it does not fit
+    let chain_id = deployments.chain_id.trim();
+    let chain_id = match chain_id.parse::<u64>() {
+        Ok(chain_id) => chain_id.to_string(),
+        Err(_) => Value::String(chain_id.to_string()).to_string(),
+    };
+
+    let mut index = format!("export const chainId = {} as const;\n", chain_id);
+    for (name, contract) in &deployments.contracts {
+        let mut module = format!("export const chainId = {} as const;\n", chain_id);
+        for field in ["address", "abi", "args"] {
+            if let Some(value) = contract.get(field) {
+                module.push_str(&format!(
+                    "export const {} = {} as const;\n",
+                    field,
+                    serde_json::to_string_pretty(value).expect("Failed to stringify")
+                ));
+            }
+        }
+        fs::write(folder.join(format!("{}.ts", name)), module).expect("failed to write file");
+        index.push_str(&format!(
+            "export * as {} from \"./{}\";\n",
+            typescript_identifier(name),
+            name
+        ));
+    }
+    fs::write(folder.join("index.ts"), index).expect("failed to write file");
+}
+
+/// deployment names can contain characters that are not allowed in identifiers
+fn typescript_identifier(name: &str) -> String {
+    let identifier: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '$' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if identifier.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", identifier)
+    } else {
+        identifier
+    }
+}
+
+/// literal type of a JSON value, the same that typescript infers for it with `as const`
+fn typescript_type(value: &Value, depth: usize) -> String {
+    let indent = "  ".repeat(depth + 1);
+    let closing_indent = "  ".repeat(depth);
+    match value {
+        Value::Array(values) if values.is_empty() => "readonly []".to_string(),
+        Value::Array(values) => format!(
+            "readonly [\n{}\n{}]",
+            values
+                .iter()
+                .map(|value| format!("{}{}", indent, typescript_type(value, depth + 1)))
+                .collect::<Vec<String>>()
+                .join(",\n"),
+            closing_indent
+        ),
+        Value::Object(object) if object.is_empty() => "{}".to_string(),
+        Value::Object(object) => format!(
+            "{{\n{}\n{}}}",
+            object
+                .iter()
+                .map(|(key, value)| format!(
+                    "{}readonly {}: {};",
+                    indent,
+                    Value::String(key.to_string()),
+                    typescript_type(value, depth + 1)
+                ))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            closing_indent
+        ),
+        _ => value.to_string(),
+    }
+}
+
+/// print the current and previous versions (kept in `.history/<name>/`) of a deployment
+pub fn print_history(
+    root_folder: &str,
+    deployments_folder: &str,
+    deployment_context: &str,
+    name: &str,
+) {
+    let folder_path_buf = Path::new(root_folder)
+        .join(deployments_folder)
+        .join(deployment_context);
+
+    let mut versions: Vec<(u64, DeploymentJSON)> = Vec::new();
+    if let Ok(dir) = fs::read_dir(folder_path_buf.join(".history").join(name)) {
+        for json_file_entry in dir.flatten() {
+            let json_filename = json_file_entry.file_name();
+            let filename = json_filename.to_str().unwrap();
+            if let Some(n) = filename
+                .strip_suffix(".json")
+                .and_then(|n| n.parse::<u64>().ok())
+            {
+                let data = fs::read_to_string(json_file_entry.path()).expect("Unable to read file");
+                let res: DeploymentJSON = serde_json::from_str(&data).expect("Unable to parse");
+                versions.push((n, res));
+            }
+        }
+    }
+    versions.sort_by_key(|(n, _)| *n);
+
+    let current = fs::read_to_string(folder_path_buf.join(format!("{}.json", name)))
+        .ok()
+        .map(|data| serde_json::from_str::<DeploymentJSON>(&data).expect("Unable to parse"));
+    if versions.is_empty() && current.is_none() {
+        println!("no deployment named {} in {}", name, deployment_context);
+        return;
+    }
+
+    let print_version = |label: String, deployment: &DeploymentJSON| {
+        println!(
+            "{}: {} (tx: {}, timestamp: {})",
+            label,
+            deployment.address,
+            deployment.tx_hash,
+            deployment
+                .timestamp
+                .map(|timestamp| timestamp.to_string())
+                .unwrap_or_else(|| "unknown".to_string())
+        );
+    };
+    for (n, deployment) in &versions {
+        print_version(n.to_string(), deployment);
+    }
+    if let Some(current) = &current {
+        print_version(
+            format!("{} (current)", current.num_deployments.max(1)),
+            current,
+        );
+    }
+}
@@ -0,0 +1,432 @@
+use std::fmt;
+
+use crate::types::DeployerDeployment;
+
+/// error returned when forge's rendering of a return value cannot be decoded
+/// `offset` is the byte offset in the rendered value where decoding failed
+#[derive(Debug, Clone)]
+pub struct ReturnsParseError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ReturnsParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at offset {})", self.message, self.offset)
+    }
+}
+
+/// what is expected right after a string field, used to know where an unquoted string ends
+#[derive(Clone, Copy)]
+enum Next {
+    Address,
+    String,
+    ChainId,
+    TupleEnd,
+}
+
+/// decode forge's rendering of a `struct DeployerDeployment[]` return value
+/// forge renders it as `[(name, 0x..., 0x..., 0x..., artifact, context, chainId), ...]`
+/// strings can be rendered quoted (`"name"`, with escapes) or bare depending on forge's version,
+/// both are supported. Bare strings are delimited using the type of the field that follows them
+pub fn parse_deployer_deployments(
+    input: &str,
+) -> Result<Vec<DeployerDeployment>, ReturnsParseError> {
+    let mut parser = Parser { input, pos: 0 };
+    let deployments = parser.deployments()?;
+    parser.skip_whitespaces();
+    if parser.pos != input.len() {
+        return Err(parser.error("unexpected trailing characters"));
+    }
+    Ok(deployments)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> ReturnsParseError {
+        ReturnsParseError {
+            offset: self.pos,
+            message: message.to_string(),
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn skip_whitespaces(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ReturnsParseError> {
+        self.skip_whitespaces();
+        match self.peek() {
+            Some(found) if found == c => {
+                self.pos += c.len_utf8();
+                Ok(())
+            }
+            Some(found) => Err(self.error(&format!("expected '{}', found '{}'", c, found))),
+            None => Err(self.error(&format!("expected '{}', found end of input", c))),
+        }
+    }
+
+    fn deployments(&mut self) -> Result<Vec<DeployerDeployment>, ReturnsParseError> {
+        let mut deployments = Vec::new();
+        self.expect('[')?;
+        self.skip_whitespaces();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(deployments);
+        }
+        loop {
+            deployments.push(self.deployment()?);
+            self.skip_whitespaces();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(deployments);
+                }
+                Some(found) => {
+                    return Err(self.error(&format!("expected ',' or ']', found '{}'", found)))
+                }
+                None => return Err(self.error("expected ',' or ']', found end of input")),
+            }
+        }
+    }
+
+    fn deployment(&mut self) -> Result<DeployerDeployment, ReturnsParseError> {
+        self.expect('(')?;
+        let name = self.string(Next::Address)?;
+        self.expect(',')?;
+        let addr = self.address()?;
+        self.expect(',')?;
+        let bytecode = self.hex()?;
+        self.expect(',')?;
+        let args = self.hex()?;
+        self.expect(',')?;
+        let artifact = self.string(Next::String)?;
+        self.expect(',')?;
+        let deployment_context = self.string(Next::ChainId)?;
+        self.expect(',')?;
+        let chain_id_as_string = self.string(Next::TupleEnd)?;
+        self.expect(')')?;
+        Ok(DeployerDeployment {
+            name,
+            addr,
+            bytecode,
+            args,
+            artifact,
+            deployment_context,
+            chain_id_as_string,
+        })
+    }
+
+    fn hex(&mut self) -> Result<String, ReturnsParseError> {
+        self.skip_whitespaces();
+        let rest = self.rest();
+        let len =
+            hex_len(rest).ok_or_else(|| self.error("expected hex value starting with '0x'"))?;
+        self.pos += len;
+        Ok(rest[..len].to_string())
+    }
+
+    fn address(&mut self) -> Result<String, ReturnsParseError> {
+        self.skip_whitespaces();
+        let rest = self.rest();
+        match hex_len(rest) {
+            Some(42) => {
+                self.pos += 42;
+                Ok(rest[..42].to_string())
+            }
+            _ => Err(self.error("expected a 20 bytes address")),
+        }
+    }
+
+    fn string(&mut self, next: Next) -> Result<String, ReturnsParseError> {
+        self.skip_whitespaces();
+        if self.peek() == Some('"') {
+            self.quoted_string()
+        } else {
+            self.bare_string(next)
+        }
+    }
+
+    fn quoted_string(&mut self) -> Result<String, ReturnsParseError> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(value);
+                }
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 'r')) => value.push('\r'),
+                    Some((_, 't')) => value.push('\t'),
+                    Some((_, '0')) => value.push('\0'),
+                    Some((_, 'u')) => {
+                        let escape_start = self.pos + i;
+                        let mut code = String::new();
+                        match chars.next() {
+                            Some((_, '{')) => {}
+                            _ => return Err(self.error_at(escape_start, "invalid unicode escape")),
+                        }
+                        loop {
+                            match chars.next() {
+                                Some((_, '}')) => break,
+                                Some((_, c)) if c.is_ascii_hexdigit() => code.push(c),
+                                _ => {
+                                    return Err(
+                                        self.error_at(escape_start, "invalid unicode escape")
+                                    )
+                                }
+                            }
+                        }
+                        let c = u32::from_str_radix(&code, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| self.error_at(escape_start, "invalid unicode escape"))?;
+                        value.push(c);
+                    }
+                    Some((_, c)) => value.push(c),
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+        Err(self.error_at(start, "unterminated string"))
+    }
+
+    fn bare_string(&mut self, next: Next) -> Result<String, ReturnsParseError> {
+        let rest = self.rest();
+        let end = match next {
+            Next::TupleEnd => rest
+                .match_indices(')')
+                .map(|(i, _)| i)
+                .find(|i| closes_tuple(&rest[i + 1..])),
+            Next::String => rest.find(','),
+            Next::ChainId => {
+                // the context is whatever remains before the `, <digits>)` closing the tuple
+                let mut candidates = rest
+                    .match_indices(',')
+                    .map(|(i, _)| i)
+                    .filter(|i| chain_id_len(&rest[i + 1..]).is_some());
+                let end = candidates.next();
+                if let Some(end) = end {
+                    // another match before the next tuple's address means the context could
+                    // also extend up to it
+                    let tuple_end = end + 1 + chain_id_len(&rest[end + 1..]).unwrap_or(0);
+                    let next_tuple = rest[tuple_end..]
+                        .match_indices(',')
+                        .map(|(i, _)| tuple_end + i)
+                        .find(|i| starts_with_address(&rest[i + 1..]))
+                        .unwrap_or(rest.len());
+                    if candidates.any(|i| i < next_tuple) {
+                        return Err(self.error("ambiguous string, could not locate the chain id"));
+                    }
+                }
+                end
+            }
+            Next::Address => rest
+                .match_indices(',')
+                .map(|(i, _)| i)
+                .find(|i| starts_with_address(&rest[i + 1..])),
+        };
+        match end {
+            Some(end) => {
+                self.pos += end;
+                Ok(rest[..end].trim_end().to_string())
+            }
+            None => Err(self.error("could not find the end of the string")),
+        }
+    }
+
+    fn error_at(&self, offset: usize, message: &str) -> ReturnsParseError {
+        ReturnsParseError {
+            offset,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// length of the `0x` prefixed hex value at the start of `s`
+fn hex_len(s: &str) -> Option<usize> {
+    if !s.starts_with("0x") {
+        return None;
+    }
+    Some(2 + s[2..].chars().take_while(|c| c.is_ascii_hexdigit()).count())
+}
+
+fn starts_with_address(s: &str) -> bool {
+    let s = s.trim_start();
+    hex_len(s) == Some(42) && s[42..].trim_start().starts_with(',')
+}
+
+/// length up to and including the closing `)` if `s` starts with the last field of a tuple:
+/// a chain id, optionally quoted, followed by the end of the tuple
+fn chain_id_len(s: &str) -> Option<usize> {
+    let trimmed = s.trim_start();
+    let (value, quoted) = match trimmed.strip_prefix('"') {
+        Some(value) => (value, true),
+        None => (trimmed, false),
+    };
+    let digits = value.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits == 0 {
+        return None;
+    }
+    let mut after = &value[digits..];
+    if quoted {
+        after = after.strip_prefix('"')?;
+    }
+    let after = after.trim_start();
+    if after.starts_with(')') && closes_tuple(&after[1..]) {
+        Some(s.len() - after.len() + 1)
+    } else {
+        None
+    }
+}
+
+/// whether what follows a `)` is consistent with the end of a tuple in the array
+fn closes_tuple(s: &str) -> bool {
+    let s = s.trim_start();
+    s.starts_with(']') || (s.starts_with(',') && s[1..].trim_start().starts_with('('))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "0x5FbDB2315678afecb367f032d93F642f64180aa3";
+
+    #[test]
+    fn parses_quoted_strings_with_special_characters() {
+        let input = format!(
+            r#"[("a, (b) \"c\" \u{{e9}}", {}, 0x6080, 0x, "src/A.sol:A", "local", "31337")]"#,
+            ADDRESS
+        );
+        let deployments = parse_deployer_deployments(&input).unwrap();
+        assert_eq!(deployments.len(), 1);
+        assert_eq!(deployments[0].name, "a, (b) \"c\" é");
+        assert_eq!(deployments[0].addr, ADDRESS);
+        assert_eq!(deployments[0].bytecode, "0x6080");
+        assert_eq!(deployments[0].args, "0x");
+        assert_eq!(deployments[0].artifact, "src/A.sol:A");
+        assert_eq!(deployments[0].deployment_context, "local");
+        assert_eq!(deployments[0].chain_id_as_string, "31337");
+    }
+
+    #[test]
+    fn parses_bare_forge_rendering() {
+        let input = format!(
+            "[(Registry, {}, 0x6080, 0x0001, GreetingsRegistry.sol:GreetingsRegistry, sepolia, 11155111), (Other, {}, 0x60, 0x, Other.sol, sepolia, 11155111)]",
+            ADDRESS, ADDRESS
+        );
+        let deployments = parse_deployer_deployments(&input).unwrap();
+        assert_eq!(deployments.len(), 2);
+        assert_eq!(deployments[0].name, "Registry");
+        assert_eq!(deployments[0].args, "0x0001");
+        assert_eq!(
+            deployments[0].artifact,
+            "GreetingsRegistry.sol:GreetingsRegistry"
+        );
+        assert_eq!(deployments[0].chain_id_as_string, "11155111");
+        assert_eq!(deployments[1].name, "Other");
+        assert_eq!(deployments[1].artifact, "Other.sol");
+    }
+
+    #[test]
+    fn bare_names_can_contain_separators() {
+        let input = format!("[(a, b (c), {}, 0x60, 0x, A.sol, local, 31337)]", ADDRESS);
+        let deployments = parse_deployer_deployments(&input).unwrap();
+        assert_eq!(deployments[0].name, "a, b (c)");
+    }
+
+    #[test]
+    fn bare_contexts_can_contain_separators() {
+        let input = format!(
+            "[(A, {}, 0x60, 0x, A.sol:A, eu, west (2), 31337), (B, {}, 0x60, 0x, B.sol, local, 1)]",
+            ADDRESS, ADDRESS
+        );
+        let deployments = parse_deployer_deployments(&input).unwrap();
+        assert_eq!(deployments[0].artifact, "A.sol:A");
+        assert_eq!(deployments[0].deployment_context, "eu, west (2)");
+        assert_eq!(deployments[0].chain_id_as_string, "31337");
+        assert_eq!(deployments[1].deployment_context, "local");
+    }
+
+    #[test]
+    fn reports_an_ambiguous_bare_context() {
+        let input = format!("[(A, {}, 0x60, 0x, A.sol, a, 1), (b, 2)]", ADDRESS);
+        let err = parse_deployer_deployments(&input).unwrap_err();
+        assert_eq!(err.offset, input.find(" a, 1").unwrap() + 1);
+        assert_eq!(err.message, "ambiguous string, could not locate the chain id");
+    }
+
+    #[test]
+    fn parses_empty_arrays() {
+        assert!(parse_deployer_deployments("[]").unwrap().is_empty());
+        assert!(parse_deployer_deployments(" [ ] ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn reports_the_offset_of_an_invalid_address() {
+        let input = r#"[("a", 0x1234, 0x, 0x, "A.sol", "local", "31337")]"#;
+        let err = parse_deployer_deployments(input).unwrap_err();
+        assert_eq!(err.offset, input.find("0x1234").unwrap());
+        assert_eq!(err.message, "expected a 20 bytes address");
+    }
+
+    #[test]
+    fn reports_the_offset_of_a_missing_separator() {
+        let input = format!(r#"[("a", {} 0x, 0x, "A.sol", "local", "31337")]"#, ADDRESS);
+        let err = parse_deployer_deployments(&input).unwrap_err();
+        assert_eq!(err.offset, input.find(" 0x,").unwrap() + 1);
+        assert_eq!(err.message, "expected ',', found '0'");
+    }
+
+    #[test]
+    fn reports_the_offset_of_an_unterminated_string() {
+        let input = r#"[("abc"#;
+        let err = parse_deployer_deployments(input).unwrap_err();
+        assert_eq!(err.offset, 2);
+        assert_eq!(err.message, "unterminated string");
+    }
+
+    #[test]
+    fn reports_the_offset_of_an_invalid_unicode_escape() {
+        let input = format!(
+            r#"[("a\u{{zz}}", {}, 0x, 0x, "A.sol", "local", "1")]"#,
+            ADDRESS
+        );
+        let err = parse_deployer_deployments(&input).unwrap_err();
+        assert_eq!(err.offset, input.find("\\u").unwrap());
+        assert_eq!(err.message, "invalid unicode escape");
+    }
+
+    #[test]
+    fn reports_trailing_characters() {
+        let err = parse_deployer_deployments("[] x").unwrap_err();
+        assert_eq!(err.offset, 3);
+        assert_eq!(err.message, "unexpected trailing characters");
+    }
+
+    #[test]
+    fn reports_a_missing_opening_bracket() {
+        let err = parse_deployer_deployments("(").unwrap_err();
+        assert_eq!(err.offset, 0);
+        assert_eq!(err.message, "expected '[', found '('");
+    }
+}
@@ -1,11 +1,14 @@
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
 
 use std::path::{Path, PathBuf};
 
+pub mod abi_decode;
 pub mod deployer;
 pub mod forge_broadcasts;
 pub mod forge_deploy_deployments;
+pub mod forge_returns;
 pub mod src_artifacts;
+pub mod staging;
 pub mod sync;
 pub mod types;
 
@@ -26,8 +29,10 @@ enum Commands {
     Sync(SyncArgs),
     /// Generate Deployer Helper with Artifacts found
     GenDeployer(GenDeployerArgs),
-    /// Export deployments for a particular context
+    /// Export deployments of a context, or of several contexts keyed by chain id
     Export(ExportArgs),
+    /// List the previous versions of a deployment
+    History(HistoryArgs),
 }
 
 #[derive(clap::Args)]
@@ -38,6 +43,42 @@ struct SyncArgs {
     deployments: Option<String>,
     #[arg(short, long)]
     artifacts: Option<String>,
+    /// timestamp of the broadcast run to sync instead of the latest one
+    #[arg(long, conflicts_with = "all_runs")]
+    run: Option<String>,
+    /// replay every broadcast run in timestamp order
+    #[arg(long)]
+    all_runs: bool,
+    /// fail instead of keeping the newest deployment when several broadcasts record the same deployment
+    #[arg(long)]
+    fail_on_conflict: bool,
+    /// only sync broadcasts of scripts matching this glob (e.g. `Deploy*.s.sol`), can be repeated
+    #[arg(long)]
+    script: Vec<String>,
+    /// only sync deployments on this chain id, can be repeated
+    #[arg(long)]
+    chain: Vec<String>,
+    /// only sync deployments in this deployment context, can be repeated
+    #[arg(long)]
+    context: Vec<String>,
+    /// report the deployment files that would be created or modified without writing them, exit with an error if there are any
+    #[arg(long)]
+    dry_run: bool,
+    /// only warn when an artifact in the out folder do not match the bytecode recorded in the broadcast
+    #[arg(long)]
+    allow_stale: bool,
+    /// consider an artifact stale when only its metadata hash differs
+    #[arg(long)]
+    strict_metadata: bool,
+    /// keep previous versions of redeployed contracts in `<context>/.history/<name>/`
+    #[arg(long)]
+    history: bool,
+    /// delete the deployments ignored by the script instead of archiving them in `<context>/.history/<name>/`
+    #[arg(long)]
+    delete_ignored: bool,
+    /// bind existing contexts to the chain of the synced deployments instead of failing when they differ
+    #[arg(long)]
+    rebind_chain: bool,
 }
 
 #[derive(clap::Args)]
@@ -53,13 +94,36 @@ struct GenDeployerArgs {
 }
 
 #[derive(clap::Args)]
-struct ExportArgs {
+struct HistoryArgs {
     deployment_context: String,
-    output: String,
+    name: String,
+    #[arg(short, long)]
+    deployments: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct ExportArgs {
+    /// context to export (a comma separated list or a glob exports several), followed by the output files
+    /// (comma separated, `.ts`, `.js`, `.cjs`, `.d.ts` or json, a path ending with `/` gets a module per contract)
+    /// with `--all`, only the output files are given
+    #[arg(value_names = ["DEPLOYMENT_CONTEXT", "OUTPUT"], num_args = 1..=2, required = true)]
+    args: Vec<String>,
+    /// export every context in a file keyed by chain id and context name, as hardhat-deploy's `export-all`
+    #[arg(long)]
+    all: bool,
     #[arg(short, long)]
     deployments: Option<String>,
     #[arg(long)]
     include_args: Option<bool>,
+    /// only export contracts whose name matches this glob, can be repeated
+    #[arg(long)]
+    include: Vec<String>,
+    /// do not export contracts whose name matches this glob, can be repeated
+    #[arg(long)]
+    exclude: Vec<String>,
+    /// prune the exported ABIs, can be repeated
+    #[arg(long, value_enum)]
+    abi_filter: Vec<forge_deploy_deployments::AbiFilter>,
 }
 
 fn main() {
@@ -67,21 +131,16 @@ fn main() {
 
     match &cli.command {
         Some(command) => match command {
-            Commands::Sync(args) => sync(
-                &cli.root,
-                &args.broadcasts,
-                &args.deployments,
-                &args.artifacts,
-            ),
+            Commands::Sync(args) => sync(&cli.root, args),
             Commands::GenDeployer(args) => {
                 gen_deployer(&cli.root, &args.templates, &args.sources, &args.output)
             }
-            Commands::Export(args) => export(
+            Commands::Export(args) => export(&cli.root, args),
+            Commands::History(args) => history(
                 &cli.root,
                 &args.deployment_context,
-                &args.output,
+                &args.name,
                 &args.deployments,
-                args.include_args.unwrap_or(false),
             ),
         },
         None => top(),
@@ -105,7 +164,7 @@ fn gen_deployer(
     let template_paths = if let Some(templates) = templates {
         templates
             .split(",")
-            .map(|v| PathBuf::from(v))
+            .map(PathBuf::from)
             .collect::<Vec<PathBuf>>()
     } else {
         Vec::new()
@@ -113,44 +172,166 @@ fn gen_deployer(
     deployer::generate_deployer(&contracts, &template_paths, generated_folder_path);
 }
 
-fn sync(
-    root: &Option<String>,
-    broadcasts: &Option<String>,
-    deployments: &Option<String>,
-    artifacts: &Option<String>,
-) {
+fn sync(root: &Option<String>, args: &SyncArgs) {
     let root_folder = root.as_deref().unwrap_or(".");
-    let broadcasts_folder = broadcasts.as_deref().unwrap_or("broadcast");
-    let deployments_folder = deployments.as_deref().unwrap_or("deployments");
-    let artifacts_folder = artifacts.as_deref().unwrap_or("out");
+    let broadcasts_folder = args.broadcasts.as_deref().unwrap_or("broadcast");
+    let deployments_folder = args.deployments.as_deref().unwrap_or("deployments");
+    let artifacts_folder = args.artifacts.as_deref().unwrap_or("out");
+
+    let run = if args.all_runs {
+        forge_broadcasts::BroadcastRun::All
+    } else if let Some(timestamp) = &args.run {
+        forge_broadcasts::BroadcastRun::Timestamp(timestamp.to_string())
+    } else {
+        forge_broadcasts::BroadcastRun::Latest
+    };
 
-    let new_deployments = forge_broadcasts::get_last_deployments(root_folder, broadcasts_folder);
-    sync::generate_deployments(
+    let filter = forge_broadcasts::SyncFilter {
+        scripts: args
+            .script
+            .iter()
+            .map(|script| {
+                glob::Pattern::new(script)
+                    .unwrap_or_else(|err| panic!("invalid script pattern {}: {}", script, err))
+            })
+            .collect(),
+        chains: args.chain.clone(),
+        contexts: args.context.clone(),
+    };
+
+    let new_deployments =
+        forge_broadcasts::get_last_deployments(root_folder, broadcasts_folder, &run, &filter);
+    forge_broadcasts::report_conflicts(&new_deployments.conflicts);
+    if args.fail_on_conflict && !new_deployments.conflicts.is_empty() {
+        eprintln!(
+            "{} conflicting deployments found",
+            new_deployments.conflicts.len()
+        );
+        std::process::exit(1);
+    }
+    // dry runs do not write anything and thus do not need to lock the deployments folder
+    let _lock = if args.dry_run {
+        None
+    } else {
+        Some(staging::DeploymentsLock::acquire(
+            &Path::new(root_folder).join(deployments_folder),
+        ))
+    };
+    let changes = sync::generate_deployments(
         root_folder,
         deployments_folder,
         artifacts_folder,
         &new_deployments,
+        &filter,
+        &sync::SyncOptions {
+            dry_run: args.dry_run,
+            allow_stale: args.allow_stale,
+            strict_metadata: args.strict_metadata,
+            history: args.history,
+            delete_ignored: args.delete_ignored,
+            rebind_chain: args.rebind_chain,
+        },
     );
+    sync::report_changes(&changes);
+    if args.dry_run && !changes.is_empty() {
+        eprintln!("{} deployment files would be changed", changes.len());
+        std::process::exit(1);
+    }
 }
 
-fn export(
+fn export(root: &Option<String>, args: &ExportArgs) {
+    let root_folder = root.as_deref().unwrap_or(".");
+    let deployments_folder = args.deployments.as_deref().unwrap_or("deployments");
+    let name_patterns = |globs: &Vec<String>| -> Vec<glob::Pattern> {
+        globs
+            .iter()
+            .map(|name| glob::Pattern::new(name).expect("invalid contract name pattern"))
+            .collect()
+    };
+    let options = forge_deploy_deployments::ExportOptions {
+        include_args: args.include_args.unwrap_or(false),
+        include: name_patterns(&args.include),
+        exclude: name_patterns(&args.exclude),
+        abi_filters: args.abi_filter.clone(),
+    };
+
+    let (deployment_context, out) = match (args.all, args.args.as_slice()) {
+        (true, [out]) => ("*", out),
+        (false, [deployment_context, out]) => (deployment_context.as_str(), out),
+        _ => Cli::command()
+            .find_subcommand_mut("export")
+            .unwrap()
+            .error(
+                clap::error::ErrorKind::WrongNumberOfValues,
+                "export expects <DEPLOYMENT_CONTEXT> <OUTPUT>, or only <OUTPUT> with --all",
+            )
+            .exit(),
+    };
+
+    let deployments_path_buf = Path::new(root_folder).join(deployments_folder);
+    let _lock = if deployments_path_buf.is_dir() {
+        Some(staging::DeploymentsLock::acquire(&deployments_path_buf))
+    } else {
+        None
+    };
+
+    // outputs ending with `/` are folders that get a typescript module per contract
+    let (folder_outputs, file_outputs): (Vec<&str>, Vec<&str>) =
+        out.split(",").partition(|output| output.ends_with('/'));
+
+    // a single context keeps the historical format, several are keyed by chain id then context name
+    if !args.all
+        && !deployment_context.contains(',')
+        && glob::Pattern::escape(deployment_context) == deployment_context
+    {
+        let deployments = forge_deploy_deployments::get_deployments(
+            root_folder,
+            deployments_folder,
+            deployment_context,
+            &options,
+        );
+        forge_deploy_deployments::export_minimal_deployments(&deployments, file_outputs);
+        for folder in folder_outputs {
+            forge_deploy_deployments::export_typescript_modules(&deployments, Path::new(folder));
+        }
+    } else {
+        let patterns: Vec<glob::Pattern> = deployment_context
+            .split(',')
+            .map(|pattern| glob::Pattern::new(pattern).expect("invalid context pattern"))
+            .collect();
+        let deployments = forge_deploy_deployments::get_all_deployments(
+            root_folder,
+            deployments_folder,
+            &patterns,
+            &options,
+        );
+        forge_deploy_deployments::export_minimal_deployments(&deployments, file_outputs);
+        for folder in folder_outputs {
+            for context_deployments in deployments.values().flat_map(|contexts| contexts.values()) {
+                forge_deploy_deployments::export_typescript_modules(
+                    context_deployments,
+                    &Path::new(folder).join(&context_deployments.name),
+                );
+            }
+        }
+    }
+}
+
+fn history(
     root: &Option<String>,
     deployment_context: &str,
-    out: &str,
+    name: &str,
     deployments: &Option<String>,
-    include_args: bool,
 ) {
     let root_folder = root.as_deref().unwrap_or(".");
     let deployments_folder = deployments.as_deref().unwrap_or("deployments");
 
-    let deployments = forge_deploy_deployments::get_deployments(
+    forge_deploy_deployments::print_history(
         root_folder,
         deployments_folder,
         deployment_context,
-        include_args,
+        name,
     );
-
-    forge_deploy_deployments::export_minimal_deployments(&deployments, out.split(",").collect());
 }
 
 fn top() {
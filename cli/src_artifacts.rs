@@ -135,7 +135,7 @@ fn is_custom_type(t: &str) -> bool {
     if t.starts_with("fixed") {
         return false;
     }
-    return true;
+    true
 }
 
 pub fn get_contracts(root_folder: &str, sources_folder: &str) -> Vec<ContractObject> {
@@ -178,10 +178,10 @@ pub fn get_contracts(root_folder: &str, sources_folder: &str) -> Vec<ContractObj
                             }
                             contract_name_objects.push(ContractName {
                                 name: String::from(contract_name),
-                                start: start,
+                                start,
                                 end: data.len(),
                             });
-                            i = i + 1;
+                            i += 1;
                         }
                     }
                 }
@@ -196,7 +196,7 @@ pub fn get_contracts(root_folder: &str, sources_folder: &str) -> Vec<ContractObj
                         Some(found) => match found.get(1) {
                             Some(constructor) => {
                                 let result = constructor.as_str().trim();
-                                if result.eq("") {
+                                if result.is_empty() {
                                     None
                                 } else {
                                     Some(result.to_string())
@@ -215,7 +215,7 @@ pub fn get_contracts(root_folder: &str, sources_folder: &str) -> Vec<ContractObj
                 //     contract_name_object.name, parsable_constructor_string
                 // );
 
-                let args: Vec<ConstructorArgObject> = if parsable_constructor_string.eq("") {
+                let args: Vec<ConstructorArgObject> = if parsable_constructor_string.is_empty() {
                     Vec::new()
                 } else {
                     let args_split = parsable_constructor_string.split(",");
@@ -227,7 +227,7 @@ pub fn get_contracts(root_folder: &str, sources_folder: &str) -> Vec<ContractObj
                                 .map(|v| v.to_string())
                                 .collect::<Vec<String>>();
 
-                            let mut args_type = components.get(0).unwrap().to_string();
+                            let mut args_type = components.first().unwrap().to_string();
 
                             let custom_type = is_custom_type(&args_type);
 
@@ -248,12 +248,12 @@ pub fn get_contracts(root_folder: &str, sources_folder: &str) -> Vec<ContractObj
                                 }
                             };
 
-                            return ConstructorArgObject {
+                            ConstructorArgObject {
                                 name: name.to_string(),
                                 memory_type,
                                 r#type: args_type,
                                 custom_type,
-                            };
+                            }
                         })
                         .collect()
                 };
@@ -264,7 +264,7 @@ pub fn get_contracts(root_folder: &str, sources_folder: &str) -> Vec<ContractObj
                 let solidity_filepath = solidity_filepath.substring(2, solidity_filepath.len());
                 let contract = ContractObject {
                     solidity_filepath: String::from(solidity_filepath),
-                    contract_name: String::from(contract_name_object.name),
+                    contract_name: contract_name_object.name,
                     solidity_filename: String::from(entry.file_name().to_str().unwrap()),
                     constructor: ConstructorObject { args },
                 };
@@ -274,5 +274,5 @@ pub fn get_contracts(root_folder: &str, sources_folder: &str) -> Vec<ContractObj
         }
     }
     // return ContractsInfo { imports, contracts };
-    return contracts;
+    contracts
 }
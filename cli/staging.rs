@@ -0,0 +1,284 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+const LOCK_FILENAME: &str = ".lock";
+const LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// lock on the deployments folder, held while a sync or an export reads or writes it
+/// the lock file is removed when dropped
+pub struct DeploymentsLock {
+    path: PathBuf,
+}
+
+impl DeploymentsLock {
+    /// wait for other forge-deploy invocations to release the lock and fail if it takes too long
+    pub fn acquire(deployments_folder: &Path) -> DeploymentsLock {
+        fs::create_dir_all(deployments_folder).expect("could not create folder");
+        let path = deployments_folder.join(LOCK_FILENAME);
+        let start = Instant::now();
+        let mut waiting = false;
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => {
+                    fs::write(&path, std::process::id().to_string())
+                        .expect("failed to write the lock file");
+                    return DeploymentsLock { path };
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                    if start.elapsed() > LOCK_TIMEOUT {
+                        panic!(
+                            "{} is locked by another forge-deploy invocation (pid {}), remove the lock file if none is running",
+                            deployments_folder.display(),
+                            fs::read_to_string(&path).unwrap_or_default().trim()
+                        );
+                    }
+                    if !waiting {
+                        eprintln!(
+                            "waiting for another forge-deploy invocation to release {}",
+                            path.display()
+                        );
+                        waiting = true;
+                    }
+                    thread::sleep(LOCK_POLL_INTERVAL);
+                }
+                Err(err) => panic!("failed to create the lock file {}: {}", path.display(), err),
+            }
+        }
+    }
+}
+
+impl Drop for DeploymentsLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+enum Operation {
+    Write { path: PathBuf, data: String },
+    Remove { path: PathBuf },
+}
+
+impl Operation {
+    fn path(&self) -> &Path {
+        match self {
+            Operation::Write { path, .. } => path,
+            Operation::Remove { path } => path,
+        }
+    }
+}
+
+/// file writes and removals applied all at once by `commit`
+/// new contents are first written to temporary files next to their target, then the previous files are
+/// moved aside and the temporary files renamed in place. On failure, everything is restored.
+#[derive(Default)]
+pub struct StagedWrites {
+    operations: Vec<Operation>,
+}
+
+impl StagedWrites {
+    pub fn write(&mut self, path: PathBuf, data: String) {
+        self.operations.push(Operation::Write { path, data });
+    }
+
+    pub fn remove(&mut self, path: PathBuf) {
+        self.operations.push(Operation::Remove { path });
+    }
+
+    pub fn commit(self) -> Result<(), String> {
+        // write the new contents in temporary files
+        let mut temporaries: Vec<(PathBuf, PathBuf)> = Vec::new();
+        // folders created for new files, removed if the commit fails
+        let mut created_folders: Vec<PathBuf> = Vec::new();
+        for operation in &self.operations {
+            if let Operation::Write { path, data } = operation {
+                let temporary = sibling(path, "tmp");
+                let parent = path.parent().unwrap();
+                if let Some(folder) = parent
+                    .ancestors()
+                    .take_while(|folder| !folder.as_os_str().is_empty() && !folder.exists())
+                    .last()
+                {
+                    created_folders.push(folder.to_path_buf());
+                }
+                let result = fs::create_dir_all(parent).and_then(|_| fs::write(&temporary, data));
+                if let Err(err) = result {
+                    let _ = fs::remove_file(&temporary);
+                    remove_files(temporaries.iter().map(|(temporary, _)| temporary));
+                    remove_folders(&created_folders);
+                    return Err(format!("failed to write {}: {}", temporary.display(), err));
+                }
+                temporaries.push((temporary, path.to_path_buf()));
+            }
+        }
+
+        // move the files to replace or remove aside so that they can be restored
+        let mut backups: Vec<(PathBuf, PathBuf)> = Vec::new();
+        for operation in &self.operations {
+            let path = operation.path();
+            if !path.exists() {
+                continue;
+            }
+            let backup = sibling(path, "bak");
+            if let Err(err) = fs::rename(path, &backup) {
+                restore(&backups);
+                remove_files(temporaries.iter().map(|(temporary, _)| temporary));
+                remove_folders(&created_folders);
+                return Err(format!("failed to move {} aside: {}", path.display(), err));
+            }
+            backups.push((backup, path.to_path_buf()));
+        }
+
+        // move the new contents in place
+        for (i, (temporary, path)) in temporaries.iter().enumerate() {
+            if let Err(err) = fs::rename(temporary, path) {
+                remove_files(temporaries[..i].iter().map(|(_, path)| path));
+                remove_files(temporaries[i..].iter().map(|(temporary, _)| temporary));
+                restore(&backups);
+                remove_folders(&created_folders);
+                return Err(format!("failed to write {}: {}", path.display(), err));
+            }
+        }
+
+        remove_files(backups.iter().map(|(backup, _)| backup));
+        Ok(())
+    }
+}
+
+/// hidden file next to `path`, not picked up when reading the `.json` files of a folder
+fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let file_name = path.file_name().unwrap().to_string_lossy();
+    path.with_file_name(format!(
+        ".{}.{}-{}",
+        file_name,
+        extension,
+        std::process::id()
+    ))
+}
+
+fn restore(backups: &[(PathBuf, PathBuf)]) {
+    for (backup, path) in backups.iter().rev() {
+        if let Err(err) = fs::rename(backup, path) {
+            eprintln!(
+                "failed to restore {} from {}: {}",
+                path.display(),
+                backup.display(),
+                err
+            );
+        }
+    }
+}
+
+fn remove_files<'a>(paths: impl Iterator<Item = &'a PathBuf>) {
+    for path in paths {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn remove_folders(folders: &[PathBuf]) {
+    for folder in folders.iter().rev() {
+        let _ = fs::remove_dir_all(folder);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!(
+            "forge-deploy-staging-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    fn file_names(folder: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(folder)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn failing_temporary_write_leaves_files_untouched() {
+        let folder = test_folder("temporary");
+        fs::write(folder.join("existing.json"), "old").unwrap();
+        // a file where a folder is expected makes the temporary write fail
+        fs::write(folder.join("blocker"), "").unwrap();
+
+        let mut staged = StagedWrites::default();
+        staged.write(folder.join("new/a.json"), "a".to_string());
+        staged.write(folder.join("existing.json"), "new".to_string());
+        staged.write(folder.join("blocker/b.json"), "b".to_string());
+        assert!(staged.commit().is_err());
+
+        assert_eq!(file_names(&folder), vec!["blocker", "existing.json"]);
+        assert_eq!(
+            fs::read_to_string(folder.join("existing.json")).unwrap(),
+            "old"
+        );
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn failing_rename_restores_backups_and_removes_created_folders() {
+        let folder = test_folder("rename");
+        fs::write(folder.join("existing.json"), "old").unwrap();
+
+        let mut staged = StagedWrites::default();
+        staged.write(folder.join("new/a.json"), "a".to_string());
+        staged.write(folder.join("existing.json"), "new".to_string());
+        // both writes share the same temporary file, the second rename finds it already moved
+        staged.write(folder.join("existing.json"), "newer".to_string());
+        let err = staged.commit().unwrap_err();
+        assert!(
+            err.starts_with(&format!(
+                "failed to write {}",
+                folder.join("existing.json").display()
+            )),
+            "{}",
+            err
+        );
+
+        assert_eq!(file_names(&folder), vec!["existing.json"]);
+        assert_eq!(
+            fs::read_to_string(folder.join("existing.json")).unwrap(),
+            "old"
+        );
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn commits_removals_with_writes() {
+        let folder = test_folder("remove");
+        fs::write(folder.join("removed.json"), "removed").unwrap();
+        fs::write(folder.join("existing.json"), "old").unwrap();
+
+        let mut staged = StagedWrites::default();
+        staged.remove(folder.join("removed.json"));
+        staged.write(folder.join("existing.json"), "new".to_string());
+        staged.commit().unwrap();
+
+        assert_eq!(file_names(&folder), vec!["existing.json"]);
+        assert_eq!(
+            fs::read_to_string(folder.join("existing.json")).unwrap(),
+            "new"
+        );
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
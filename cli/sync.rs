@@ -1,63 +1,1005 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 
-use crate::types::{ArtifactJSON, DeploymentJSON, DeploymentObject};
+use regex::Regex;
+use serde_json::Value;
+use tiny_keccak::{Hasher, Keccak};
 
+use crate::abi_decode::decode_constructor_args;
+use crate::forge_broadcasts::{BroadcastDeployments, SyncFilter, UPGRADED_EVENT_TOPIC};
+use crate::staging::StagedWrites;
+use crate::types::{
+    ArtifactJSON, BytecodeJSON, DeploymentJSON, DeploymentObject, ImplementationUpgrade,
+    ProxyUpgrade,
+};
+
+/// what a sync changes in a deployment file
+pub enum DeploymentChangeKind {
+    New {
+        address: String,
+    },
+    AddressChanged {
+        old: String,
+        new: String,
+    },
+    AbiChanged,
+    ArgsChanged,
+    ImplementationUpgraded {
+        implementation: String,
+    },
+    /// any other field (receipt, bytecode, ...)
+    Updated,
+    /// ignored by the script and not replaced
+    Archived {
+        to: PathBuf,
+    },
+    Deleted,
+}
+
+pub struct DeploymentChange {
+    pub path: PathBuf,
+    pub kinds: Vec<DeploymentChangeKind>,
+}
+
+#[derive(Default)]
+pub struct SyncOptions {
+    /// nothing is written, the returned changes are the ones pending
+    pub dry_run: bool,
+    /// write deployments even if their artifact do not match the bytecode recorded in the broadcast
+    pub allow_stale: bool,
+    /// also compare the metadata hash appended to the bytecode when checking artifacts
+    pub strict_metadata: bool,
+    /// keep the previous version of redeployed contracts in the `.history` folder of the context
+    pub history: bool,
+    /// delete the deployments ignored by the script instead of archiving them in the `.history` folder
+    pub delete_ignored: bool,
+    /// overwrite the `.chainId` of existing contexts instead of rejecting deployments from another chain
+    pub rebind_chain: bool,
+}
+
+/// generate the deployment files and return the ones that were created or modified
 pub fn generate_deployments(
     root_folder: &str,
     deployment_folder: &str,
     artifacts_folder: &str,
-    new_deployments: &HashMap<String, DeploymentObject>,
-) {
+    broadcast_deployments: &BroadcastDeployments,
+    filter: &SyncFilter,
+    options: &SyncOptions,
+) -> Vec<DeploymentChange> {
+    let new_deployments = &broadcast_deployments.deployments;
     let out_folder_path_buf = Path::new(root_folder).join(deployment_folder);
     let artifact_folder_path_buf = Path::new(root_folder).join(artifacts_folder);
 
+    // every file is computed first so that the changes can be reported before anything is written
+    let mut chain_ids: BTreeMap<PathBuf, String> = BTreeMap::new();
+    let mut files: BTreeMap<PathBuf, DeploymentJSON> = BTreeMap::new();
+    // solc inputs are written once per hash, in the `solcInputs` folder of each context
+    let mut solc_inputs: BTreeMap<PathBuf, Value> = BTreeMap::new();
+    let mut stale_deployments: Vec<String> = Vec::new();
+    let mut chain_mismatches: Vec<String> = Vec::new();
+    let mut snapshots: BTreeSet<String> = BTreeSet::new();
+    let mut history_files: BTreeMap<PathBuf, DeploymentJSON> = BTreeMap::new();
+    let mut proxied_deployments: Vec<&DeploymentObject> = Vec::new();
     for (_key, value) in new_deployments.iter() {
+        if !filter.matches_context(&value.deployment_context) {
+            continue;
+        }
         let folder_path_buf = out_folder_path_buf.join(value.deployment_context.as_str());
-        fs::create_dir_all(&folder_path_buf).expect("could not create folder");
+        // a context is bound to the chain of its first deployment, as checked by the Deployer
         let chainid_file_path_buf = folder_path_buf.join(".chainId");
-        if !chainid_file_path_buf.exists() {
-            fs::write(chainid_file_path_buf, &value.chain_id)
-                .expect("failed to write the .chainId file");
+        if let Some(pending_chain_id) = chain_ids.get(&chainid_file_path_buf) {
+            if !pending_chain_id.eq(&value.chain_id) {
+                chain_mismatches.push(format!(
+                    "{}/{} is on chain {} but other deployments synced in the same context are on chain {}",
+                    value.deployment_context, value.name, value.chain_id, pending_chain_id
+                ));
+            }
+        } else {
+            match fs::read_to_string(&chainid_file_path_buf) {
+                Ok(bound_chain_id) if !bound_chain_id.trim().eq(&value.chain_id) => {
+                    if options.rebind_chain {
+                        eprintln!(
+                            "rebinding context {} from chain {} to chain {}",
+                            value.deployment_context,
+                            bound_chain_id.trim(),
+                            value.chain_id
+                        );
+                        chain_ids.insert(chainid_file_path_buf, value.chain_id.to_string());
+                    } else {
+                        chain_mismatches.push(format!(
+                            "{}/{} is on chain {} but the context is bound to chain {}",
+                            value.deployment_context,
+                            value.name,
+                            value.chain_id,
+                            bound_chain_id.trim()
+                        ));
+                    }
+                }
+                Ok(_) => {}
+                Err(_) => {
+                    chain_ids.insert(chainid_file_path_buf, value.chain_id.to_string());
+                }
+            }
         }
 
-        // unfortunately forge do not export artifacts in the broadcast file, so we have to fetch in the out folder
+        // proxied deployments are generated from their `_Implementation` and `_Proxy` deployments once these are known
+        if value.artifact_path.is_empty() {
+            proxied_deployments.push(value);
+            continue;
+        }
+
+        // unfortunately forge do not export artifacts in the broadcast file, so unless the script saved them
+        // in a snapshot folder for its run, we have to fetch in the out folder
         // if sync is called not directly, out folder could be out of sync and we would get wrong artifact data
-        // TODO save artifact in the solidity execution in a specific temporary folder (identified with a unique id)
-        // and fetch artifact data from there.
+        // this is detected by comparing the artifact's bytecode with the one recorded by the Deployer
 
         // The following assume this is fixed: https://github.com/foundry-rs/foundry/issues/4760
-        let artifact_solidity_folder_path_buf = artifact_folder_path_buf.join(&value.artifact_path);
-        let contract_filename = match value.contract_name.clone() {
-            Some(name) => format!("{}.json", name),
-            None => {
-                let mut res = fs::read_dir(&artifact_solidity_folder_path_buf).unwrap(); // .filter(|f| f.unwrap().file_name().to_str().unwrap().ends_with(()));
-                res.next()
-                    .unwrap()
-                    .unwrap()
-                    .file_name()
-                    .to_str()
-                    .unwrap()
-                    .to_string()
-            }
-        };
-        let artifact_path_buf = artifact_solidity_folder_path_buf.join(contract_filename);
-        let data = fs::read_to_string(artifact_path_buf).expect("Unable to read file");
-        let artifact: ArtifactJSON = serde_json::from_str(&data).expect("Unable to parse");
+        let snapshot_solidity_folder_path_buf = value.artifacts_snapshot.as_ref().map(|snapshot| {
+            snapshots.insert(snapshot.to_string());
+            Path::new(root_folder)
+                .join(snapshot)
+                .join(&value.artifact_path)
+        });
+        let artifact_solidity_folder_path_buf = match snapshot_solidity_folder_path_buf {
+            Some(folder) if folder.is_dir() => folder,
+            _ => artifact_folder_path_buf.join(&value.artifact_path),
+        };
+        let artifact = match &value.contract_name {
+            Some(name) => {
+                read_artifact(&artifact_solidity_folder_path_buf.join(format!("{}.json", name)))
+            }
+            None => match find_artifact_by_bytecode(&artifact_solidity_folder_path_buf, value) {
+                Some(artifact) => artifact,
+                None => {
+                    // without the contract name, a stale artifact cannot be told apart from a missing one
+                    stale_deployments.push(format!("{}/{}", value.deployment_context, value.name));
+                    if options.allow_stale {
+                        eprintln!(
+                            "no artifact in {} matches the bytecode of {} ({}), it will not be recorded",
+                            artifact_solidity_folder_path_buf.display(),
+                            value.name,
+                            value.address
+                        );
+                    }
+                    continue;
+                }
+            },
+        };
+        if !bytecode_matches(
+            &artifact.bytecode,
+            &value.bytecode,
+            !options.strict_metadata,
+        ) {
+            stale_deployments.push(format!("{}/{}", value.deployment_context, value.name));
+        }
+
+        // forge only gives the arguments as strings, and not for every transaction type
+        let args = match decode_constructor_args(&artifact.abi, &value.args_data) {
+            Ok(args) => Some(args),
+            Err(err) => {
+                eprintln!(
+                    "could not decode the constructor arguments of {}: {}",
+                    value.name, err
+                );
+                value
+                    .args
+                    .as_ref()
+                    .map(|args| args.iter().cloned().map(Value::String).collect())
+            }
+        };
+        let init_code = format!(
+            "{}{}",
+            value.bytecode,
+            value
+                .args_data
+                .strip_prefix("0x")
+                .unwrap_or(&value.args_data)
+        );
+        if !value.data.eq_ignore_ascii_case(&init_code) {
+            eprintln!(
+                "the creation data of {} is not its bytecode followed by its constructor arguments",
+                value.name
+            );
+        }
 
         let file_path_buf = folder_path_buf.join(format!("{}.json", value.name));
+        let (implementation_history, num_deployments) =
+            previous_deployment(&file_path_buf, value, options, &mut history_files);
+
+        // hardhat-deploy stores the metadata as a string, forge gives it as is only with `extra_output = ["metadata"]`
+        let metadata = artifact.raw_metadata.clone().or_else(|| {
+            artifact
+                .metadata
+                .as_ref()
+                .map(|metadata| serde_json::to_string(metadata).expect("Failed to stringify"))
+        });
+        let metadata_output = artifact
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get("output"));
+        let devdoc = artifact
+            .devdoc
+            .clone()
+            .or_else(|| metadata_output.and_then(|output| output.get("devdoc").cloned()));
+        let userdoc = artifact
+            .userdoc
+            .clone()
+            .or_else(|| metadata_output.and_then(|output| output.get("userdoc").cloned()));
+
+        let libraries = link_libraries(&artifact, value, &folder_path_buf, new_deployments);
+        let link_references = artifact.bytecode.link_references.clone();
+
+        // the hash is only recorded when `solcInputs/<hash>.json` exists or could be rebuilt
+        let solc_input_hash = artifact.metadata.as_ref().and_then(|metadata| {
+            let hash = solc_input_hash(metadata);
+            let solc_input_path_buf = folder_path_buf
+                .join("solcInputs")
+                .join(format!("{}.json", hash));
+            if solc_input_path_buf.exists() || solc_inputs.contains_key(&solc_input_path_buf) {
+                return Some(hash);
+            }
+            match solc_input(Path::new(root_folder), metadata) {
+                Ok(input) => {
+                    solc_inputs.insert(solc_input_path_buf, input);
+                    Some(hash)
+                }
+                Err(err) => {
+                    eprintln!(
+                        "could not rebuild the solc input of {}: {}",
+                        value.name, err
+                    );
+                    None
+                }
+            }
+        });
+
+        files.insert(
+            file_path_buf,
+            DeploymentJSON {
+                address: value.address.to_string(),
+                abi: artifact.abi,
+                bytecode: value.bytecode.to_string(),
+                args_data: value.args_data.to_string(),
+                args,
+                data: value.data.to_string(),
+                tx_hash: value.tx_hash.to_string(),
+                receipt: value.receipt.clone(),
+                creation: Some(value.creation.clone()),
+                implementation_history,
+                libraries,
+                link_references,
+                transaction_hash: value.tx_hash.to_string(),
+                block_number: value.receipt.as_ref().map(|receipt| receipt.block_number),
+                transaction_index: value
+                    .receipt
+                    .as_ref()
+                    .map(|receipt| receipt.transaction_index),
+                deployed_bytecode: artifact
+                    .deployed_bytecode
+                    .as_ref()
+                    .map(|bytecode| bytecode.object.to_string()),
+                solc_input_hash,
+                metadata,
+                devdoc,
+                userdoc,
+                storage_layout: artifact.storage_layout.clone(),
+                implementation: None,
+                num_deployments,
+                timestamp: Some(value.timestamp),
+            },
+        );
+    }
+
+    // hardhat-deploy layout: `<name>.json` has the proxy address with the implementation and proxy ABIs merged
+    for value in proxied_deployments {
+        let folder_path_buf = out_folder_path_buf.join(value.deployment_context.as_str());
+        let companion = |suffix: &str| {
+            let path = folder_path_buf.join(format!("{}_{}.json", value.name, suffix));
+            files.get(&path).cloned().or_else(|| read_deployment(&path))
+        };
+        let (implementation, proxy) = match (companion("Implementation"), companion("Proxy")) {
+            (Some(implementation), Some(proxy))
+                if proxy.address.eq_ignore_ascii_case(&value.address) =>
+            {
+                (implementation, proxy)
+            }
+            _ => {
+                eprintln!(
+                    "{} at {} has no artifact and no matching {}_Proxy and {}_Implementation deployments, it will not be recorded",
+                    value.name, value.address, value.name, value.name
+                );
+                continue;
+            }
+        };
+        let file_path_buf = folder_path_buf.join(format!("{}.json", value.name));
+        let (implementation_history, num_deployments) =
+            previous_deployment(&file_path_buf, value, options, &mut history_files);
+        files.insert(
+            file_path_buf,
+            DeploymentJSON {
+                address: value.address.to_string(),
+                abi: merge_abis(&implementation.abi, &proxy.abi),
+                tx_hash: proxy.tx_hash,
+                transaction_hash: proxy.transaction_hash,
+                block_number: proxy.block_number,
+                transaction_index: proxy.transaction_index,
+                receipt: proxy.receipt,
+                creation: proxy.creation,
+                implementation: Some(implementation.address.to_string()),
+                implementation_history,
+                num_deployments,
+                timestamp: Some(value.timestamp),
+                ..implementation
+            },
+        );
+    }
+
+    if !chain_mismatches.is_empty() {
+        chain_mismatches.sort();
+        for mismatch in &chain_mismatches {
+            eprintln!("{}", mismatch);
+        }
+        panic!(
+            "{} deployments do not match the chain of their context, use another context or sync with --rebind-chain",
+            chain_mismatches.len()
+        );
+    }
+
+    if !stale_deployments.is_empty() {
+        stale_deployments.sort();
+        for name in &stale_deployments {
+            eprintln!(
+                "artifact of {} do not match the bytecode recorded in the broadcast",
+                name
+            );
+        }
+        if !options.allow_stale {
+            panic!(
+                "{} deployments have stale artifacts, run `forge build` or sync with --allow-stale",
+                stale_deployments.len()
+            );
+        }
+    }
+
+    record_upgrades(
+        &out_folder_path_buf,
+        &broadcast_deployments.upgrades,
+        filter,
+        &chain_ids,
+        &mut files,
+    );
+
+    let mut changes: Vec<DeploymentChange> = Vec::new();
+
+    // deployments ignored by the script are archived (or deleted) unless they were replaced
+    let mut ignored_files: Vec<PathBuf> = Vec::new();
+    let mut ignored_keys = broadcast_deployments
+        .ignored
+        .keys()
+        .collect::<Vec<&String>>();
+    ignored_keys.sort();
+    for key in ignored_keys {
+        let ignored = &broadcast_deployments.ignored[key];
+        if !filter.matches_context(&ignored.deployment_context) {
+            continue;
+        }
+        let folder_path_buf = out_folder_path_buf.join(ignored.deployment_context.as_str());
+        let file_path_buf = folder_path_buf.join(format!("{}.json", ignored.name));
+        if files.contains_key(&file_path_buf) {
+            continue;
+        }
+        let existing = match read_deployment(&file_path_buf) {
+            Some(existing) => existing,
+            None => continue,
+        };
+        let kind = if options.delete_ignored {
+            DeploymentChangeKind::Deleted
+        } else {
+            let history_path_buf = history_path(
+                &folder_path_buf,
+                &ignored.name,
+                existing.num_deployments.max(1),
+            );
+            history_files.insert(history_path_buf.clone(), existing);
+            DeploymentChangeKind::Archived {
+                to: history_path_buf,
+            }
+        };
+        changes.push(DeploymentChange {
+            path: file_path_buf.clone(),
+            kinds: vec![kind],
+        });
+        ignored_files.push(file_path_buf);
+    }
+
+    // all the files are written at once so that a failure do not leave the deployments half updated
+    let mut staged = StagedWrites::default();
+    for (history_path_buf, deployment) in history_files {
+        let data = serde_json::to_string_pretty(&deployment).expect("Failed to stringify");
+        staged.write(history_path_buf, data);
+    }
+    for file_path_buf in ignored_files {
+        staged.remove(file_path_buf);
+    }
+    for (file_path_buf, deployment) in files {
+        let data = serde_json::to_string_pretty(&deployment).expect("Failed to stringify");
+        let existing_data = fs::read_to_string(&file_path_buf).ok();
+        if existing_data.as_deref() == Some(data.as_str()) {
+            continue;
+        }
+        let existing = existing_data
+            .map(|existing_data| serde_json::from_str(&existing_data).expect("Unable to parse"));
+        changes.push(DeploymentChange {
+            kinds: diff_deployment(existing.as_ref(), &deployment),
+            path: file_path_buf.clone(),
+        });
+        staged.write(file_path_buf, data);
+    }
+    for (solc_input_path_buf, input) in solc_inputs {
+        let data = serde_json::to_string_pretty(&input).expect("Failed to stringify");
+        staged.write(solc_input_path_buf, data);
+    }
+    for (chainid_file_path_buf, chain_id) in chain_ids {
+        staged.write(chainid_file_path_buf, chain_id);
+    }
+
+    if !options.dry_run {
+        staged
+            .commit()
+            .unwrap_or_else(|err| panic!("{}, the deployments were left unchanged", err));
+        // a filtered sync could leave deployments of the run unsynced, their snapshot is then kept
+        if filter.is_empty() {
+            remove_snapshots(Path::new(root_folder), &snapshots);
+        }
+    }
+    changes
+}
+
+/// identify the compilation input with the hash of the language, sources (that include their keccak256) and settings of the metadata
+fn solc_input_hash(metadata: &Value) -> String {
+    let input = serde_json::json!({
+        "language": metadata.get("language"),
+        "sources": metadata.get("sources"),
+        "settings": metadata.get("settings"),
+    });
+    keccak256_hex(serde_json::to_string(&input).unwrap().as_bytes())
+}
+
+fn keccak256_hex(data: &[u8]) -> String {
+    let mut hash = [0u8; 32];
+    let mut hasher = Keccak::v256();
+    hasher.update(data);
+    hasher.finalize(&mut hash);
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// rebuild the solc standard JSON input from the artifact metadata
+/// sources content is taken from the metadata when compiled with `useLiteralContent`, else from the project files
+/// which must still match the keccak256 recorded at compilation
+fn solc_input(root_folder: &Path, metadata: &Value) -> Result<Value, String> {
+    let mut sources = serde_json::Map::new();
+    let metadata_sources = metadata
+        .get("sources")
+        .and_then(Value::as_object)
+        .ok_or("no sources in metadata")?;
+    for (path, source) in metadata_sources {
+        let content = match source.get("content").and_then(Value::as_str) {
+            Some(content) => content.to_string(),
+            None => fs::read_to_string(root_folder.join(path))
+                .map_err(|err| format!("could not read source {}: {}", path, err))?,
+        };
+        if let Some(keccak256) = source.get("keccak256").and_then(Value::as_str) {
+            if !keccak256
+                .trim_start_matches("0x")
+                .eq_ignore_ascii_case(&keccak256_hex(content.as_bytes()))
+            {
+                return Err(format!("source {} changed since compilation", path));
+            }
+        }
+        sources.insert(path.to_string(), serde_json::json!({ "content": content }));
+    }
+
+    let mut settings = metadata
+        .get("settings")
+        .and_then(Value::as_object)
+        .cloned()
+        .unwrap_or_default();
+    settings.remove("compilationTarget");
+    // the metadata uses `<path>:<name>` keys for libraries while the input expects them grouped by path
+    if let Some(libraries) = settings
+        .remove("libraries")
+        .and_then(|libraries| libraries.as_object().cloned())
+    {
+        let mut input_libraries = serde_json::Map::new();
+        for (key, address) in libraries {
+            let (path, name) = key.rsplit_once(':').unwrap_or(("", key.as_str()));
+            input_libraries
+                .entry(path.to_string())
+                .or_insert_with(|| Value::Object(serde_json::Map::new()))
+                .as_object_mut()
+                .unwrap()
+                .insert(name.to_string(), address);
+        }
+        settings.insert("libraries".to_string(), Value::Object(input_libraries));
+    }
+    settings.insert(
+        "outputSelection".to_string(),
+        serde_json::json!({
+            "*": {
+                "*": ["abi", "evm.bytecode", "evm.deployedBytecode", "evm.methodIdentifiers", "metadata"],
+                "": ["ast"]
+            }
+        }),
+    );
+
+    Ok(serde_json::json!({
+        "language": metadata.get("language").cloned().unwrap_or_else(|| Value::from("Solidity")),
+        "sources": sources,
+        "settings": settings,
+    }))
+}
+
+fn diff_deployment(
+    existing: Option<&DeploymentJSON>,
+    deployment: &DeploymentJSON,
+) -> Vec<DeploymentChangeKind> {
+    let existing = match existing {
+        Some(existing) => existing,
+        None => {
+            return vec![DeploymentChangeKind::New {
+                address: deployment.address.to_string(),
+            }]
+        }
+    };
+    let mut kinds = Vec::new();
+    if !existing.address.eq_ignore_ascii_case(&deployment.address) {
+        kinds.push(DeploymentChangeKind::AddressChanged {
+            old: existing.address.to_string(),
+            new: deployment.address.to_string(),
+        });
+    }
+    if existing.abi != deployment.abi {
+        kinds.push(DeploymentChangeKind::AbiChanged);
+    }
+    if existing.args_data != deployment.args_data || existing.args != deployment.args {
+        kinds.push(DeploymentChangeKind::ArgsChanged);
+    }
+    for upgrade in deployment
+        .implementation_history
+        .iter()
+        .skip(existing.implementation_history.len())
+    {
+        kinds.push(DeploymentChangeKind::ImplementationUpgraded {
+            implementation: upgrade.new_implementation.to_string(),
+        });
+    }
+    if kinds.is_empty() {
+        kinds.push(DeploymentChangeKind::Updated);
+    }
+    kinds
+}
+
+pub fn report_changes(changes: &[DeploymentChange]) {
+    for change in changes {
+        let summary = change
+            .kinds
+            .iter()
+            .map(|kind| match kind {
+                DeploymentChangeKind::New { address } => format!("new deployment at {}", address),
+                DeploymentChangeKind::AddressChanged { old, new } => {
+                    format!("address changed from {} to {}", old, new)
+                }
+                DeploymentChangeKind::AbiChanged => "abi changed".to_string(),
+                DeploymentChangeKind::ArgsChanged => "args changed".to_string(),
+                DeploymentChangeKind::ImplementationUpgraded { implementation } => {
+                    format!("implementation upgraded to {}", implementation)
+                }
+                DeploymentChangeKind::Updated => "updated".to_string(),
+                DeploymentChangeKind::Archived { to } => {
+                    format!("ignored, archived to {}", to.display())
+                }
+                DeploymentChangeKind::Deleted => "ignored, deleted".to_string(),
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        println!("{}: {}", change.path.display(), summary);
+    }
+}
+
+/// resolve the libraries linked in the artifact's bytecode
+/// the address is taken from the libraries deployed by forge in the broadcast,
+/// then from the deployments of the same context named after the library
+/// and finally from the creation code at the link offsets
+fn link_libraries(
+    artifact: &ArtifactJSON,
+    deployment: &DeploymentObject,
+    context_folder: &Path,
+    new_deployments: &HashMap<String, DeploymentObject>,
+) -> BTreeMap<String, String> {
+    let mut libraries: BTreeMap<String, String> = BTreeMap::new();
+    for (path, references) in &artifact.bytecode.link_references {
+        for (name, offsets) in references {
+            let address = deployment
+                .libraries
+                .get(&format!("{}:{}", path, name))
+                .cloned()
+                .or_else(|| {
+                    new_deployments
+                        .get(&format!("{}::{}", deployment.deployment_context, name))
+                        .map(|library| library.address.to_string())
+                })
+                .or_else(|| {
+                    read_deployment(&context_folder.join(format!("{}.json", name)))
+                        .map(|library| library.address)
+                })
+                .or_else(|| {
+                    offsets
+                        .first()
+                        .and_then(|offset| {
+                            deployment
+                                .data
+                                .get(2 + offset.start * 2..2 + (offset.start + offset.length) * 2)
+                        })
+                        .filter(|address| address.chars().all(|c| c.is_ascii_hexdigit()))
+                        .map(|address| format!("0x{}", address))
+                });
+            match address {
+                Some(address) => {
+                    libraries.insert(name.to_string(), address);
+                }
+                None => eprintln!(
+                    "could not resolve the address of library {}:{} linked in {}",
+                    path, name, deployment.name
+                ),
+            }
+        }
+    }
+    libraries
+}
+
+/// the implementation history is kept as long as the deployment address do not change
+/// while numDeployments counts, as in hardhat-deploy, how many times the deployment address changed
+fn previous_deployment(
+    file_path: &Path,
+    deployment: &DeploymentObject,
+    options: &SyncOptions,
+    history_files: &mut BTreeMap<PathBuf, DeploymentJSON>,
+) -> (Vec<ImplementationUpgrade>, u64) {
+    match read_deployment(file_path) {
+        Some(existing) if existing.address.eq_ignore_ascii_case(&deployment.address) => (
+            existing.implementation_history,
+            existing.num_deployments.max(1),
+        ),
+        Some(existing) => {
+            let num_deployments = existing.num_deployments.max(1);
+            // in history mode the previous deployment is kept in `.history/<name>/<numDeployments>.json`
+            if options.history {
+                history_files.insert(
+                    history_path(
+                        file_path.parent().unwrap(),
+                        &deployment.name,
+                        num_deployments,
+                    ),
+                    existing,
+                );
+            }
+            (Vec::new(), num_deployments + 1)
+        }
+        None => (Vec::new(), 1),
+    }
+}
+
+/// the implementation ABI followed by the proxy's own functions, events and errors
+/// the proxy constructor and the entries already in the implementation ABI are left out
+fn merge_abis(implementation_abi: &[Value], proxy_abi: &[Value]) -> Vec<Value> {
+    let signature = |entry: &Value| {
+        let types = entry
+            .get("inputs")
+            .and_then(Value::as_array)
+            .map(|inputs| {
+                inputs
+                    .iter()
+                    .map(|input| input.get("type").and_then(Value::as_str).unwrap_or(""))
+                    .collect::<Vec<&str>>()
+                    .join(",")
+            })
+            .unwrap_or_default();
+        format!(
+            "{} {}({})",
+            entry.get("type").and_then(Value::as_str).unwrap_or(""),
+            entry.get("name").and_then(Value::as_str).unwrap_or(""),
+            types
+        )
+    };
+    let mut abi = implementation_abi.to_vec();
+    let mut signatures = abi.iter().map(signature).collect::<BTreeSet<String>>();
+    for entry in proxy_abi {
+        if entry.get("type").and_then(Value::as_str) == Some("constructor") {
+            continue;
+        }
+        if signatures.insert(signature(entry)) {
+            abi.push(entry.clone());
+        }
+    }
+    abi
+}
+
+/// `.history/<name>/<n>.json`, n being bumped if already used (the name could have been archived before)
+fn history_path(context_folder: &Path, name: &str, n: u64) -> PathBuf {
+    let folder = context_folder.join(".history").join(name);
+    let mut n = n;
+    while folder.join(format!("{}.json", n)).exists() {
+        n += 1;
+    }
+    folder.join(format!("{}.json", n))
+}
+
+/// remove the artifacts snapshots once synced, only folders inside the root folder are removed
+fn remove_snapshots(root_folder: &Path, snapshots: &BTreeSet<String>) {
+    for snapshot in snapshots {
+        let snapshot_path = Path::new(snapshot);
+        if snapshot_path.is_absolute()
+            || snapshot_path
+                .components()
+                .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+        {
+            eprintln!(
+                "not removing artifacts snapshot outside of the root folder: {}",
+                snapshot
+            );
+            continue;
+        }
+        let snapshot_path_buf = root_folder.join(snapshot_path);
+        if snapshot_path_buf.is_dir() {
+            fs::remove_dir_all(&snapshot_path_buf).unwrap_or_else(|err| {
+                panic!(
+                    "failed to remove artifacts snapshot {}: {}",
+                    snapshot_path_buf.display(),
+                    err
+                )
+            });
+        }
+    }
+}
 
-        let data = serde_json::to_string_pretty(&DeploymentJSON {
-            address: value.address.to_string(),
-            abi: artifact.abi,
-            bytecode: value.bytecode.to_string(),
-            args_data: value.args_data.to_string(),
-            args: value.args.clone(),
-            data: value.data.to_string(),
-            tx_hash: value.tx_hash.to_string(),
+fn read_artifact(artifact_path: &Path) -> ArtifactJSON {
+    let data = fs::read_to_string(artifact_path)
+        .unwrap_or_else(|err| panic!("Unable to read {}: {}", artifact_path.display(), err));
+    serde_json::from_str(&data).expect("Unable to parse")
+}
+
+/// when the artifact string do not name the contract, the artifact is the one in the solidity file's folder
+/// whose creation code matches the deployed one (the metadata hash and the linked libraries being ignored)
+/// none if no artifact matches, which means the artifacts are stale
+fn find_artifact_by_bytecode(folder: &Path, deployment: &DeploymentObject) -> Option<ArtifactJSON> {
+    let entries = fs::read_dir(folder)
+        .unwrap_or_else(|err| panic!("Unable to read {}: {}", folder.display(), err));
+    let mut matches: Vec<(String, ArtifactJSON)> = Vec::new();
+    for entry in entries.flatten() {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if !file_name.ends_with(".json") {
+            continue;
+        }
+        let artifact = read_artifact(&entry.path());
+        if bytecode_matches(&artifact.bytecode, &deployment.bytecode, true) {
+            matches.push((file_name, artifact));
+        }
+    }
+    match matches.len() {
+        0 => None,
+        1 => matches.pop().map(|(_, artifact)| artifact),
+        _ => panic!(
+            "several artifacts in {} match the bytecode of {} ({}): {}, specify the contract name in the artifact string",
+            folder.display(),
+            deployment.name,
+            deployment.address,
+            matches
+                .iter()
+                .map(|(file_name, _)| file_name.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        ),
+    }
+}
+
+fn bytecode_matches(
+    artifact_bytecode: &BytecodeJSON,
+    bytecode: &str,
+    ignore_metadata: bool,
+) -> bool {
+    let expected = artifact_bytecode
+        .object
+        .trim_start_matches("0x")
+        .to_lowercase();
+    let actual = bytecode.trim_start_matches("0x").to_lowercase();
+    if expected.len() != actual.len() {
+        return false;
+    }
+    // library placeholders are replaced by the library addresses when linked
+    let mut ignored = vec![false; expected.len()];
+    for references in artifact_bytecode.link_references.values() {
+        for offsets in references.values() {
+            for offset in offsets {
+                for i in offset.start * 2..(offset.start + offset.length) * 2 {
+                    if i < ignored.len() {
+                        ignored[i] = true;
+                    }
+                }
+            }
+        }
+    }
+    if ignore_metadata {
+        for code in [&expected, &actual] {
+            for range in metadata_ranges(code) {
+                for i in range {
+                    ignored[i] = true;
+                }
+            }
+        }
+    }
+    expected
+        .bytes()
+        .zip(actual.bytes())
+        .zip(ignored)
+        .all(|((e, a), ignored)| ignored || e == a)
+}
+
+/// positions of the CBOR encoded metadata solc appends to the runtime code (ipfs, bzzr1 and bzzr0 variants)
+/// there can be several of them, for example when the contract creates other contracts
+fn metadata_ranges(bytecode: &str) -> Vec<std::ops::Range<usize>> {
+    let metadata_regex = Regex::new(
+        "a264697066735822[0-9a-f]{68}64736f6c6343[0-9a-f]{6}0033|a265627a7a72315820[0-9a-f]{64}64736f6c6343[0-9a-f]{6}0032|a165627a7a72305820[0-9a-f]{64}0029",
+    )
+    .unwrap();
+    metadata_regex
+        .find_iter(bytecode)
+        .filter(|found| found.start() % 2 == 0)
+        .map(|found| found.range())
+        .collect()
+}
+
+fn read_deployment(file_path: &Path) -> Option<DeploymentJSON> {
+    let data = fs::read_to_string(file_path).ok()?;
+    Some(serde_json::from_str(&data).expect("Unable to parse"))
+}
+
+/// implementation of a proxy before its first recorded upgrade
+/// taken from the `_Implementation` deployment on disk, else from the `Upgraded` log of the proxy's creation
+fn known_implementation(
+    file_path_buf: &Path,
+    proxy: &DeploymentJSON,
+    new_implementation: &str,
+) -> Option<String> {
+    let name = file_path_buf.file_stem()?.to_string_lossy();
+    let name = name.strip_suffix("_Proxy").unwrap_or(&name);
+    let implementation_path_buf =
+        file_path_buf.with_file_name(format!("{}_Implementation.json", name));
+    if let Some(implementation) = read_deployment(&implementation_path_buf) {
+        if !implementation
+            .address
+            .eq_ignore_ascii_case(new_implementation)
+        {
+            return Some(implementation.address.to_lowercase());
+        }
+    }
+    proxy
+        .receipt
+        .as_ref()?
+        .logs
+        .iter()
+        .filter(|log| {
+            log.address.eq_ignore_ascii_case(&proxy.address)
+                && log.topics.len() == 2
+                && log.topics[0].eq_ignore_ascii_case(UPGRADED_EVENT_TOPIC)
+                && log.topics[1].len() >= 40
         })
-        .expect("Failed to stringify");
-        fs::write(file_path_buf, data).expect("failed to write file");
+        .map(|log| format!("0x{}", &log.topics[1][log.topics[1].len() - 40..]).to_lowercase())
+        .next_back()
+        .filter(|implementation| !implementation.eq_ignore_ascii_case(new_implementation))
+}
+
+/// append the upgrades to the implementation history of the deployments (in contexts on the same chain) that are the upgraded proxies
+/// `files` holds the deployments about to be written, the ones on disk are added to it when they get upgraded
+fn record_upgrades(
+    deployments_folder: &Path,
+    upgrades: &[ProxyUpgrade],
+    filter: &SyncFilter,
+    chain_ids: &BTreeMap<PathBuf, String>,
+    files: &mut BTreeMap<PathBuf, DeploymentJSON>,
+) {
+    if upgrades.is_empty() {
+        return;
+    }
+    let mut context_folders: BTreeSet<PathBuf> = files
+        .keys()
+        .filter_map(|file_path_buf| file_path_buf.parent().map(Path::to_path_buf))
+        .collect();
+    if deployments_folder.is_dir() {
+        for context_dir in fs::read_dir(deployments_folder).unwrap().flatten() {
+            if context_dir.path().is_dir() {
+                context_folders.insert(context_dir.path());
+            }
+        }
+    }
+    for context_folder in context_folders {
+        if !filter.matches_context(&context_folder.file_name().unwrap().to_string_lossy()) {
+            continue;
+        }
+        let chainid_file_path_buf = context_folder.join(".chainId");
+        let chain_id = match chain_ids
+            .get(&chainid_file_path_buf)
+            .cloned()
+            .or_else(|| fs::read_to_string(&chainid_file_path_buf).ok())
+        {
+            Some(chain_id) => chain_id,
+            None => continue,
+        };
+        let context_upgrades = upgrades
+            .iter()
+            .filter(|upgrade| upgrade.chain_id.as_deref() == Some(chain_id.trim()))
+            .collect::<Vec<&ProxyUpgrade>>();
+        if context_upgrades.is_empty() {
+            continue;
+        }
+        let mut file_paths: BTreeSet<PathBuf> = files
+            .keys()
+            .filter(|file_path_buf| file_path_buf.parent() == Some(context_folder.as_path()))
+            .cloned()
+            .collect();
+        if context_folder.is_dir() {
+            for json_file_entry in fs::read_dir(&context_folder).unwrap().flatten() {
+                if json_file_entry.path().is_file()
+                    && json_file_entry
+                        .file_name()
+                        .to_string_lossy()
+                        .ends_with(".json")
+                {
+                    file_paths.insert(json_file_entry.path());
+                }
+            }
+        }
+        for file_path_buf in file_paths {
+            let mut deployment = match files.get(&file_path_buf) {
+                Some(deployment) => deployment.clone(),
+                None => match read_deployment(&file_path_buf) {
+                    Some(deployment) => deployment,
+                    None => continue,
+                },
+            };
+            let mut modified = false;
+            for upgrade in &context_upgrades {
+                if !upgrade.proxy.eq_ignore_ascii_case(&deployment.address) {
+                    continue;
+                }
+                let already_recorded = deployment.implementation_history.iter().any(|entry| {
+                    entry.tx_hash.eq_ignore_ascii_case(&upgrade.tx_hash)
+                        && entry
+                            .new_implementation
+                            .eq_ignore_ascii_case(&upgrade.implementation)
+                });
+                if already_recorded {
+                    continue;
+                }
+                let old_implementation = match deployment.implementation_history.last() {
+                    Some(entry) => Some(entry.new_implementation.to_string()),
+                    None => {
+                        known_implementation(&file_path_buf, &deployment, &upgrade.implementation)
+                    }
+                };
+                deployment
+                    .implementation_history
+                    .push(ImplementationUpgrade {
+                        old_implementation,
+                        new_implementation: upgrade.implementation.to_string(),
+                        tx_hash: upgrade.tx_hash.to_string(),
+                        block_number: upgrade.block_number,
+                    });
+                modified = true;
+            }
+            if modified {
+                files.insert(file_path_buf, deployment);
+            }
+        }
     }
 }
@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 use serde_json;
 use serde_json::Value;
@@ -38,6 +40,18 @@ pub struct ContractObject {
 
 // ------------------------------------------------------------------------------------------------
 
+/// mirror of the solidity `DeployerDeployment` struct returned by `Deployer.newDeployments()`
+#[derive(Debug, Clone, Default)]
+pub struct DeployerDeployment {
+    pub name: String,
+    pub addr: String,
+    pub bytecode: String,
+    pub args: String,
+    pub artifact: String,
+    pub deployment_context: String,
+    pub chain_id_as_string: String,
+}
+
 pub struct DeploymentObject {
     pub name: String,
     pub address: String,
@@ -50,11 +64,46 @@ pub struct DeploymentObject {
     pub artifact_path: String,
     pub deployment_context: String,
     pub chain_id: String,
+    pub receipt: Option<DeploymentReceipt>,
+    pub creation: DeploymentCreation,
+    pub libraries: BTreeMap<String, String>,
+    pub broadcast_file: String,
+    pub timestamp: u64,
+    /// folder (relative to the root) where the script saved the artifacts of its run, if any
+    pub artifacts_snapshot: Option<String>,
 }
 
+/// how a contract was created: `method` is either CREATE or CREATE2
+/// `factory` is the contract that performed the creation, if not created directly by the transaction
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct DeploymentCreation {
+    pub method: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub factory: Option<String>,
+}
+
+/// a proxy that was pointed to a new implementation
+#[derive(Debug, Clone)]
+pub struct ProxyUpgrade {
+    pub chain_id: Option<String>,
+    pub proxy: String,
+    pub implementation: String,
+    pub tx_hash: String,
+    pub block_number: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct LinkReference {
+    pub start: usize,
+    pub length: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all(deserialize = "camelCase", serialize = "camelCase"))]
 pub struct BytecodeJSON {
     pub object: String,
+    #[serde(default)]
+    pub link_references: BTreeMap<String, BTreeMap<String, Vec<LinkReference>>>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
@@ -65,10 +114,17 @@ pub struct ASTJSON {
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all(deserialize = "camelCase", serialize = "camelCase"))]
 pub struct ArtifactJSON {
     pub abi: Vec<Value>,
     pub bytecode: BytecodeJSON,
+    pub deployed_bytecode: Option<BytecodeJSON>,
     pub metadata: Option<Value>,
+    /// only present when forge is configured with `extra_output = ["metadata"]`
+    pub raw_metadata: Option<String>,
+    pub storage_layout: Option<Value>,
+    pub devdoc: Option<Value>,
+    pub userdoc: Option<Value>,
     pub ast: ASTJSON,
 }
 
@@ -88,6 +144,47 @@ pub struct ABIConstructor {
     pub r#type: String,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all(deserialize = "camelCase", serialize = "camelCase"))]
+pub struct DeploymentLog {
+    pub transaction_index: u64,
+    pub block_number: u64,
+    pub transaction_hash: String,
+    pub address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+    pub log_index: u64,
+    pub block_hash: String,
+}
+
+/// transaction receipt as stored by hardhat-deploy
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all(deserialize = "camelCase", serialize = "camelCase"))]
+pub struct DeploymentReceipt {
+    pub to: Option<String>,
+    pub from: String,
+    pub contract_address: Option<String>,
+    pub transaction_index: u64,
+    pub gas_used: String,
+    pub logs_bloom: String,
+    pub block_hash: String,
+    pub transaction_hash: String,
+    pub logs: Vec<DeploymentLog>,
+    pub block_number: u64,
+    pub cumulative_gas_used: String,
+    pub status: u64,
+    pub byzantium: bool,
+}
+
+/// entry of a proxy's implementation history
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct ImplementationUpgrade {
+    pub old_implementation: Option<String>,
+    pub new_implementation: String,
+    pub tx_hash: String,
+    pub block_number: Option<u64>,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct DeploymentJSON {
     pub address: String,
@@ -95,6 +192,51 @@ pub struct DeploymentJSON {
     pub bytecode: String,
     pub args_data: String,
     pub tx_hash: String,
-    pub args: Option<Vec<String>>,
+    /// constructor arguments decoded with the constructor ABI
+    pub args: Option<Vec<Value>>,
     pub data: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<DeploymentReceipt>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub creation: Option<DeploymentCreation>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub implementation_history: Vec<ImplementationUpgrade>,
+    /// address of the libraries linked in the deployed contract, keyed by library name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub libraries: BTreeMap<String, String>,
+    /// positions in the bytecode where the libraries addresses are inserted, keyed by path and name
+    #[serde(
+        rename = "linkReferences",
+        default,
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub link_references: BTreeMap<String, BTreeMap<String, Vec<LinkReference>>>,
+    // hardhat-deploy fields
+    #[serde(rename = "transactionHash", default)]
+    pub transaction_hash: String,
+    /// block of the deployment transaction, where indexers start from
+    #[serde(rename = "blockNumber", skip_serializing_if = "Option::is_none")]
+    pub block_number: Option<u64>,
+    #[serde(rename = "transactionIndex", skip_serializing_if = "Option::is_none")]
+    pub transaction_index: Option<u64>,
+    #[serde(rename = "deployedBytecode", skip_serializing_if = "Option::is_none")]
+    pub deployed_bytecode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<String>,
+    #[serde(rename = "solcInputHash", skip_serializing_if = "Option::is_none")]
+    pub solc_input_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub devdoc: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub userdoc: Option<Value>,
+    #[serde(rename = "storageLayout", skip_serializing_if = "Option::is_none")]
+    pub storage_layout: Option<Value>,
+    /// for proxied deployments, the address of the current implementation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub implementation: Option<String>,
+    #[serde(rename = "numDeployments", default)]
+    pub num_deployments: u64,
+    /// timestamp of the broadcast that recorded the deployment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
 }
@@ -77,6 +77,7 @@ interface Deployer {
     function prankStatus() external view returns (bool active, address addr);
 
     /// @notice function that return all new deployments as an array
+    /// deployments ignored via `ignoreDeployment` are included with the zero address
     function newDeployments() external view returns (DeployerDeployment[] memory);
 
     /// @notice function that tell you whether a deployment already exists with that name
@@ -230,6 +231,7 @@ contract GlobalDeployer is Deployer {
     }
 
     /// @notice function that return all new deployments as an array
+    /// deployments ignored via `ignoreDeployment` are included with the zero address
     function newDeployments() external view returns (DeployerDeployment[] memory) {
         return _newDeployments;
     }
@@ -275,6 +277,18 @@ contract GlobalDeployer is Deployer {
     function ignoreDeployment(string memory name) public {
         _namedDeployments[name].name = "";
         _namedDeployments[name].addr = payable(address(1)); // TO ensure it is picked up as being ignored
+        // recorded with the zero address so that forge-deploy sync removes it from disk if it is not replaced
+        _newDeployments.push(
+            DeployerDeployment({
+                name: name,
+                addr: payable(address(0)),
+                bytecode: "",
+                args: "",
+                artifact: "",
+                deploymentContext: deploymentContext,
+                chainIdAsString: chainIdAsString
+            })
+        );
     }
 
     /// @notice function that return the deployment (address, bytecode and args bytes used)